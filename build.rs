@@ -1,4 +1,4 @@
-use std::{env, net::Ipv4Addr};
+use std::{env, net::IpAddr};
 
 fn main() {
     let auth_server_ip = env!(
@@ -7,6 +7,6 @@ fn main() {
     );
     println!("ENV AUTH_SERVER_IP = {auth_server_ip}");
     auth_server_ip
-        .parse::<Ipv4Addr>()
+        .parse::<IpAddr>()
         .expect("AUTH_SERVER_IP invalid");
 }
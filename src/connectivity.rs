@@ -0,0 +1,171 @@
+use crate::{SrunClient, in_netns, outln, srun::SrunError, utils};
+use serde::Deserialize;
+use std::{
+    net::{IpAddr, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+const TCP_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_HTTP_URL: &str = "http://connect.rom.miui.com/generate_204";
+
+/// A single way to tell whether the network is already connected.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Check {
+    /// TCP connect to `addr`, e.g. `baidu.com:80`.
+    Tcp { addr: String },
+    /// GET a `generate_204` style url, any other status or a redirect means captive.
+    Http { url: String },
+    /// Resolve `host`, private or loopback answers mean the DNS is hijacked.
+    /// The system resolver can't be bound, so it fails on a bound client.
+    Dns { host: String },
+    /// Ask the portal's `rad_user_info` whether this ip is online.
+    Portal,
+}
+
+/// How the results of several checks are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckRule {
+    #[default]
+    Any,
+    All,
+}
+
+impl Check {
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::Portal,
+            Self::Http {
+                url: DEFAULT_HTTP_URL.to_string(),
+            },
+        ]
+    }
+
+    pub fn run(&self, client: &SrunClient) -> bool {
+        let result = in_netns(client.netns(), || match self {
+            Self::Tcp { addr } => client
+                .connector()
+                .map_err(|e| e.to_string())
+                .and_then(|connector| {
+                    utils::tcp_ping(&connector, addr, TCP_TIMEOUT).map_err(|e| e.to_string())
+                })
+                .map(|d| format!("connected in {}ms", d)),
+            Self::Http { url } => match client.http_status(url) {
+                Ok(204) => Ok("got 204".to_string()),
+                Ok(code) => Err(format!("got {}, captive portal", code)),
+                Err(e) => Err(e.to_string()),
+            },
+            Self::Dns { .. } if client.connector().is_ok_and(|c| c.is_bound()) => {
+                Err("not bound to the dial, use tcp or http".to_string())
+            }
+            Self::Dns { host } => match (host.as_str(), 0).to_socket_addrs() {
+                Ok(addrs) => {
+                    let addrs: Vec<IpAddr> = addrs.map(|a| a.ip()).collect();
+                    match addrs.iter().find(|ip| is_hijacked(ip)) {
+                        Some(ip) => Err(format!("resolved to {}, hijacked", ip)),
+                        None if addrs.is_empty() => Err("no address".to_string()),
+                        None => Ok(format!("resolved to {:?}", addrs)),
+                    }
+                }
+                Err(e) => Err(e.to_string()),
+            },
+            Self::Portal => match client.user_info() {
+                Ok(info) if info.error == "ok" => Ok(format!("{} online", info.user_name)),
+                Ok(info) => Err(info.error),
+                Err(e) => Err(e.to_string()),
+            },
+        })
+        .unwrap_or_else(|e| Err(e.to_string()));
+        match result {
+            Ok(msg) => {
+                outln!("check {}: ok, {}", self, msg);
+                true
+            }
+            Err(msg) => {
+                outln!("check {}: failed, {}", self, msg);
+                false
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Tcp { addr } => write!(f, "tcp:{}", addr),
+            Self::Http { url } => write!(f, "http:{}", url),
+            Self::Dns { host } => write!(f, "dns:{}", host),
+            Self::Portal => f.write_str("portal"),
+        }
+    }
+}
+
+impl FromStr for Check {
+    type Err = SrunError;
+
+    /// `tcp:HOST:PORT`, `http:URL`, `dns:HOST` or `portal`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = s.split_once(':').unwrap_or((s, ""));
+        match (kind, arg) {
+            ("tcp", addr) if !addr.is_empty() => Ok(Self::Tcp {
+                addr: addr.to_string(),
+            }),
+            ("http", url) if !url.is_empty() => Ok(Self::Http {
+                url: url.to_string(),
+            }),
+            ("dns", host) if !host.is_empty() => Ok(Self::Dns {
+                host: host.to_string(),
+            }),
+            ("portal", "") => Ok(Self::Portal),
+            _ => Err(SrunError::InvalidValue("check", s.to_owned())),
+        }
+    }
+}
+
+impl FromStr for CheckRule {
+    type Err = SrunError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(Self::Any),
+            "all" => Ok(Self::All),
+            _ => Err(SrunError::InvalidValue("check rule", s.to_owned())),
+        }
+    }
+}
+
+pub(crate) fn run(client: &SrunClient, checks: &[Check], rule: CheckRule) -> bool {
+    match rule {
+        CheckRule::Any => checks.iter().any(|c| c.run(client)),
+        CheckRule::All => checks.iter().all(|c| c.run(client)),
+    }
+}
+
+// public names should not resolve to these
+fn is_hijacked(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+        }
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+        }
+    }
+}
+
+#[test]
+fn test_parse_check() {
+    assert_eq!(
+        "tcp:baidu.com:80".parse::<Check>().unwrap(),
+        Check::Tcp {
+            addr: "baidu.com:80".to_string()
+        }
+    );
+    assert_eq!("portal".parse::<Check>().unwrap(), Check::Portal);
+    assert!("tcp".parse::<Check>().is_err());
+}
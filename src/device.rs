@@ -0,0 +1,73 @@
+use crate::srun::SrunError;
+use serde::Deserialize;
+use std::{fmt, str::FromStr};
+
+/// Login fingerprints as sent by the official clients.
+///
+/// srun counts PC and mobile sessions separately, so one account can hold
+/// a PC slot and a mobile slot at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DevicePreset {
+    PcWindows,
+    Linux,
+    Macos,
+    Android,
+    Ios,
+}
+
+impl DevicePreset {
+    pub fn os(&self) -> &'static str {
+        match self {
+            Self::PcWindows => "Windows 10",
+            Self::Linux => "Linux",
+            Self::Macos => "Mac OS",
+            Self::Android => "Android",
+            Self::Ios => "iOS",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PcWindows => "Windows",
+            Self::Linux => "Linux",
+            Self::Macos => "Macintosh",
+            Self::Android | Self::Ios => "Smartphones/PDAs/Tablets",
+        }
+    }
+
+    pub fn n(&self) -> i32 {
+        200
+    }
+
+    pub fn utype(&self) -> i32 {
+        1
+    }
+}
+
+impl FromStr for DevicePreset {
+    type Err = SrunError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pc-windows" => Ok(Self::PcWindows),
+            "linux" => Ok(Self::Linux),
+            "macos" => Ok(Self::Macos),
+            "android" => Ok(Self::Android),
+            "ios" => Ok(Self::Ios),
+            _ => Err(SrunError::InvalidValue("device", s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for DevicePreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::PcWindows => "pc-windows",
+            Self::Linux => "linux",
+            Self::Macos => "macos",
+            Self::Android => "android",
+            Self::Ios => "ios",
+        })
+    }
+}
@@ -0,0 +1,91 @@
+use crate::User;
+use serde::Deserialize;
+
+/// Accounts that take turns on one interface or IP: the first that the portal
+/// accepts is used, the next is only tried on an account error like arrears.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FailoverGroup {
+    pub if_name: Option<String>,
+    pub ip: Option<String>,
+    /// Names or usernames of config users, in the order they are tried.
+    pub users: Vec<String>,
+}
+
+impl FailoverGroup {
+    fn position(&self, user: &User) -> Option<usize> {
+        self.users
+            .iter()
+            .position(|name| user.label() == name || user.username == *name)
+    }
+}
+
+/// Split users into the accounts to try for each dial, in config order.
+/// Members of a group take its `if_name` or `ip`, every other user is a dial
+/// of its own.
+pub fn group_users(users: Vec<User>, groups: &[FailoverGroup]) -> Vec<Vec<User>> {
+    let mut dials: Vec<Vec<User>> = Vec::new();
+    // index in `dials` of each group, once its first member is seen
+    let mut group_dials: Vec<Option<usize>> = vec![None; groups.len()];
+    for mut user in users {
+        let Some((g, group)) = groups
+            .iter()
+            .enumerate()
+            .find(|(_, group)| group.position(&user).is_some())
+        else {
+            dials.push(vec![user]);
+            continue;
+        };
+        if group.ip.is_some() || group.if_name.is_some() {
+            user.ip = group.ip.clone();
+            user.if_name = group.if_name.clone();
+        }
+        match group_dials[g] {
+            Some(i) => dials[i].push(user),
+            None => {
+                group_dials[g] = Some(dials.len());
+                dials.push(vec![user]);
+            }
+        }
+    }
+    for (group, dial) in groups.iter().zip(group_dials) {
+        if let Some(i) = dial {
+            dials[i].sort_by_key(|user| group.position(user));
+        }
+    }
+    dials
+}
+
+#[test]
+fn test_group_users() {
+    let groups = [FailoverGroup {
+        if_name: Some("wan0".to_owned()),
+        ip: None,
+        users: vec!["main".to_owned(), "backup".to_owned()],
+    }];
+    let users = vec![
+        User {
+            username: "other".to_owned(),
+            if_name: Some("eth0".to_owned()),
+            ..Default::default()
+        },
+        User {
+            username: "backup".to_owned(),
+            if_name: Some("eth0".to_owned()),
+            ..Default::default()
+        },
+        User {
+            username: "main".to_owned(),
+            if_name: Some("eth0".to_owned()),
+            ..Default::default()
+        },
+    ];
+    let dials = group_users(users, &groups);
+    let names: Vec<Vec<&str>> = dials
+        .iter()
+        .map(|dial| dial.iter().map(User::label).collect())
+        .collect();
+    assert_eq!(names, [vec!["other"], vec!["main", "backup"]]);
+    assert_eq!(dials[1][0].if_name.as_deref(), Some("wan0"));
+    assert_eq!(dials[0][0].if_name.as_deref(), Some("eth0"));
+}
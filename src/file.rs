@@ -1,13 +1,27 @@
-use crate::User;
+use crate::{
+    AccountPool, Check, CheckRule, DevicePreset, EncodingProfile, FailoverGroup, IpSelector,
+    KickPolicy, LogoutMode, MultiDial, Protocol, Rotation, User,
+};
 use serde::Deserialize;
-use std::{collections::LinkedList, error::Error, fs::File, io::BufReader, path::Path};
+use std::{
+    collections::LinkedList,
+    error::Error,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
 
 #[derive(Deserialize, Clone, Default)]
 #[serde(default)]
 pub struct Config {
     pub server: Option<String>,
+    /// Auth server of the IPv6 sessions, defaults to `server`.
+    pub server6: Option<String>,
     pub detect_ip: bool,
     pub strict_bind: bool,
+    #[serde(flatten)]
+    pub select: IpSelector,
+    pub bind_interface: bool,
     pub double_stack: bool,
     pub n: Option<i32>,
     #[serde(alias = "type")]
@@ -15,11 +29,58 @@ pub struct Config {
     pub acid: Option<i32>,
     pub os: Option<String>,
     pub name: Option<String>,
+    pub device: Option<DevicePreset>,
+    pub username_template: Option<String>,
     pub retry_delay: Option<u32>,
     pub retry_times: Option<u32>,
+    pub kick: bool,
+    pub kick_policy: Option<KickPolicy>,
+    pub online_path: Option<String>,
+    pub logout_mode: Option<LogoutMode>,
+    pub protocol: Option<Protocol>,
+    pub encoding: Option<EncodingProfile>,
+    pub detect_encoding: bool,
+    pub test: bool,
+    pub checks: Vec<Check>,
+    pub check_rule: CheckRule,
+    pub check_interval: Option<u64>,
+    pub watch: bool,
+    pub multidial: Option<MultiDial>,
+    /// Users logged in or out at the same time, 1 by default.
+    pub jobs: Option<usize>,
+    pub failover: Vec<FailoverGroup>,
+    /// Rotate the accounts of failover groups by quota, checked by `--continue`.
+    pub rotation: Option<Rotation>,
+    /// Accounts leased to interfaces, for more accounts than interfaces.
+    pub pool: Option<AccountPool>,
+    /// Where the state of every user is kept, `users` in the state home by default.
+    pub state_dir: Option<PathBuf>,
     users: LinkedList<User>,
 }
 
+impl Config {
+    pub fn user_count(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Hand `if_names` not taken by any user to the users without one, in order.
+    pub fn assign_if_names(&mut self, if_names: &[String]) {
+        let free: Vec<&String> = if_names
+            .iter()
+            .filter(|if_name| {
+                !self
+                    .users
+                    .iter()
+                    .any(|u| u.if_name.as_ref() == Some(if_name))
+            })
+            .collect();
+        let users = self.users.iter_mut().filter(|u| u.if_name.is_none());
+        for (user, if_name) in users.zip(free) {
+            user.if_name = Some(if_name.clone());
+        }
+    }
+}
+
 impl Iterator for Config {
     type Item = User;
 
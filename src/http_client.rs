@@ -1,34 +1,115 @@
+use crate::in_netns;
 use socket2::{Domain, Socket, Type};
-use std::net::SocketAddr;
+use std::{
+    io,
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+#[cfg(feature = "ureq")]
 use ureq::Connector;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub(crate) struct BindConnector {
-    bind_addr: SocketAddr,
+    bind_addr: Option<SocketAddr>,
+    interface: Option<String>,
+    mark: Option<u32>,
+    netns: Option<String>,
 }
 
 impl BindConnector {
-    pub fn new_bind(bind_addr: SocketAddr) -> Self {
-        Self { bind_addr }
+    pub fn set_bind_addr(mut self, bind_addr: Option<SocketAddr>) -> Self {
+        self.bind_addr = bind_addr;
+        self
     }
+
+    /// `SO_BINDTODEVICE`, a VRF device binds the socket to the whole VRF.
+    pub fn set_interface(mut self, interface: Option<String>) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// `SO_MARK`, for `ip rule fwmark` policy routing.
+    pub fn set_mark(mut self, mark: Option<u32>) -> Self {
+        self.mark = mark;
+        self
+    }
+
+    pub fn set_netns(mut self, netns: Option<String>) -> Self {
+        self.netns = netns;
+        self
+    }
+
+    /// Whether the sockets are bound to an address, a device or a mark.
+    pub fn is_bound(&self) -> bool {
+        self.bind_addr.is_some() || self.interface.is_some() || self.mark.is_some()
+    }
+
+    /// A bound address only reaches addresses of its own family.
+    pub fn can_reach(&self, addr: &SocketAddr) -> bool {
+        self.bind_addr
+            .is_none_or(|bind_addr| bind_addr.is_ipv4() == addr.is_ipv4())
+    }
+
+    fn socket(&self, addr: &SocketAddr) -> io::Result<Socket> {
+        let domain = Domain::for_address(addr.to_owned());
+        let socket = in_netns(self.netns.as_deref(), || {
+            Socket::new(domain, Type::STREAM, None)
+        })
+        .map_err(|e| io::Error::other(e.to_string()))??;
+        if let Some(ref interface) = self.interface {
+            bind_device(&socket, interface)?;
+        }
+        if let Some(mark) = self.mark {
+            set_mark(&socket, mark)?;
+        }
+        if let Some(bind_addr) = self.bind_addr {
+            socket.bind(&bind_addr.into())?;
+        }
+        Ok(socket)
+    }
+
+    pub fn connect_timeout(&self, addr: &SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        let socket = self.socket(addr)?;
+        socket.connect_timeout(&addr.to_owned().into(), timeout)?;
+        Ok(socket.into())
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: &Socket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to an interface is only supported on linux",
+    ))
 }
 
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn set_mark(socket: &Socket, mark: u32) -> io::Result<()> {
+    socket.set_mark(mark)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn set_mark(_socket: &Socket, _mark: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "fwmark is only supported on linux",
+    ))
+}
+
+#[cfg(feature = "ureq")]
 impl Connector for BindConnector {
-    fn connect(&self, addr: &std::net::SocketAddr) -> std::io::Result<std::net::TcpStream> {
-        let socket = Socket::new(Domain::for_address(addr.to_owned()), Type::STREAM, None)?;
-        socket.bind(&self.bind_addr.into())?;
+    fn connect(&self, addr: &SocketAddr) -> io::Result<TcpStream> {
+        let socket = self.socket(addr)?;
         socket.connect(&addr.to_owned().into())?;
         Ok(socket.into())
     }
 
-    fn connect_timeout(
-        &self,
-        addr: &std::net::SocketAddr,
-        timeout: std::time::Duration,
-    ) -> std::io::Result<std::net::TcpStream> {
-        let socket = Socket::new(Domain::for_address(addr.to_owned()), Type::STREAM, None)?;
-        socket.bind(&self.bind_addr.into())?;
-        socket.connect_timeout(&addr.to_owned().into(), timeout)?;
-        Ok(socket.into())
+    fn connect_timeout(&self, addr: &SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        BindConnector::connect_timeout(self, addr, timeout)
     }
 }
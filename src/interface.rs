@@ -0,0 +1,419 @@
+use crate::{Result, srun::SrunError};
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+};
+
+/// A network interface with all of its addresses.
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub name: String,
+    pub index: u32,
+    pub mac: Option<String>,
+    pub addrs: Vec<IfAddr>,
+    pub up: bool,
+    pub running: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IfAddr {
+    pub ip: IpAddr,
+    pub prefix: u8,
+}
+
+impl fmt::Display for IfAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix)
+    }
+}
+
+impl Interface {
+    pub fn is_loopback(&self) -> bool {
+        !self.addrs.is_empty() && self.addrs.iter().all(|a| a.ip.is_loopback())
+    }
+
+    /// The address srun authorizes: the first IPv4, else a global IPv6.
+    pub fn auth_ip(&self) -> Option<IpAddr> {
+        self.addrs
+            .iter()
+            .map(|a| a.ip)
+            .find(IpAddr::is_ipv4)
+            .or_else(|| self.ipv6())
+    }
+
+    /// The first IPv6 that is neither loopback nor link-local.
+    pub fn ipv6(&self) -> Option<IpAddr> {
+        self.addrs.iter().map(|a| a.ip).find(|ip| match ip {
+            IpAddr::V6(ip) => !ip.is_loopback() && !ip.is_unicast_link_local(),
+            IpAddr::V4(_) => false,
+        })
+    }
+}
+
+/// All interfaces, down ones and those without an address too, in the order
+/// the system lists them.
+pub fn interfaces() -> Result<Vec<Interface>> {
+    let mut ifs: Vec<Interface> = Vec::new();
+    let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+    for (name, index) in links() {
+        seen.insert(name.clone(), ifs.len());
+        let (up, running) = flags(&name);
+        ifs.push(Interface {
+            mac: mac(&name),
+            index,
+            name,
+            addrs: Vec::new(),
+            up,
+            running,
+        });
+    }
+    for i in if_addrs::get_if_addrs()? {
+        let addr = IfAddr {
+            ip: i.ip(),
+            prefix: match i.addr {
+                if_addrs::IfAddr::V4(ref v4) => u32::from(v4.netmask).count_ones() as u8,
+                if_addrs::IfAddr::V6(ref v6) => u128::from(v6.netmask).count_ones() as u8,
+            },
+        };
+        match seen.get(&i.name) {
+            Some(&n) => ifs[n].addrs.push(addr),
+            None => {
+                seen.insert(i.name.clone(), ifs.len());
+                let (up, running) = flags(&i.name);
+                ifs.push(Interface {
+                    mac: mac(&i.name),
+                    index: i.index.unwrap_or_default(),
+                    name: i.name,
+                    addrs: vec![addr],
+                    up,
+                    running,
+                });
+            }
+        }
+    }
+    Ok(ifs)
+}
+
+// every link, with or without an address
+#[cfg(target_os = "linux")]
+fn links() -> Vec<(String, u32)> {
+    use std::ffi::CStr;
+
+    let mut links = Vec::new();
+    unsafe {
+        let head = libc::if_nameindex();
+        if head.is_null() {
+            return links;
+        }
+        let mut i = head;
+        while (*i).if_index != 0 {
+            let name = CStr::from_ptr((*i).if_name).to_string_lossy().into_owned();
+            links.push((name, (*i).if_index));
+            i = i.add(1);
+        }
+        libc::if_freenameindex(head);
+    }
+    links
+}
+
+// elsewhere only the interfaces with an address are known
+#[cfg(not(target_os = "linux"))]
+fn links() -> Vec<(String, u32)> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn mac(name: &str) -> Option<String> {
+    let mac = std::fs::read_to_string(format!("/sys/class/net/{}/address", name)).ok()?;
+    let mac = mac.trim();
+    (!mac.is_empty() && mac != "00:00:00:00:00:00").then(|| mac.to_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mac(_name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn flags(name: &str) -> (bool, bool) {
+    let flags = std::fs::read_to_string(format!("/sys/class/net/{}/flags", name))
+        .ok()
+        .and_then(|f| u32::from_str_radix(f.trim().trim_start_matches("0x"), 16).ok())
+        .unwrap_or_default();
+    (
+        flags & libc::IFF_UP as u32 != 0,
+        flags & libc::IFF_RUNNING as u32 != 0,
+    )
+}
+
+// an interface with an address is good enough elsewhere
+#[cfg(not(target_os = "linux"))]
+fn flags(_name: &str) -> (bool, bool) {
+    (true, true)
+}
+
+/// How an `if_name` picks interfaces: `mac:02:00:00:00:00:01`, `re:^eth\d+$`,
+/// a glob with `*` and `?`, or else the exact name.
+#[derive(Debug, Clone)]
+pub enum IfMatcher {
+    Exact(String),
+    Glob(String),
+    Regex(regex_lite::Regex),
+    Mac(String),
+}
+
+impl FromStr for IfMatcher {
+    type Err = SrunError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(mac) = s.strip_prefix("mac:") {
+            return Ok(Self::Mac(mac.to_ascii_lowercase().replace('-', ":")));
+        }
+        if let Some(re) = s.strip_prefix("re:") {
+            return regex_lite::Regex::new(re)
+                .map(Self::Regex)
+                .map_err(|_| SrunError::InvalidValue("interface regex", re.to_owned()));
+        }
+        if s.contains(['*', '?']) {
+            return Ok(Self::Glob(s.to_owned()));
+        }
+        Ok(Self::Exact(s.to_owned()))
+    }
+}
+
+impl IfMatcher {
+    pub fn matches(&self, iface: &Interface) -> bool {
+        match self {
+            Self::Exact(name) => iface.name == *name,
+            Self::Glob(pattern) => glob(pattern.as_bytes(), iface.name.as_bytes()),
+            Self::Regex(re) => re.is_match(&iface.name),
+            Self::Mac(mac) => iface.mac.as_deref() == Some(mac.as_str()),
+        }
+    }
+
+    /// Matching interfaces, in inventory order.
+    pub fn find(&self, ifs: &[Interface]) -> Vec<Interface> {
+        ifs.iter().filter(|i| self.matches(i)).cloned().collect()
+    }
+}
+
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((&b'*', rest)), _) => {
+            glob(rest, name) || (!name.is_empty() && glob(pattern, &name[1..]))
+        }
+        (Some((&b'?', rest)), Some((_, name))) => glob(rest, name),
+        (Some((p, rest)), Some((n, name))) => p == n && glob(rest, name),
+        _ => false,
+    }
+}
+
+/// An IP network like `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    pub ip: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.ip, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = SrunError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || SrunError::InvalidValue("cidr", s.to_owned());
+        let (ip, prefix) = s.split_once('/').ok_or_else(invalid)?;
+        let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+        let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { ip, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Picks the IP to authorize without asking, every given rule has to match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IpSelector {
+    /// Addresses inside this network.
+    pub ip_in: Option<Cidr>,
+    /// The source address the kernel would use to reach this host, usually the auth server.
+    pub ip_route_to: Option<String>,
+    /// Addresses of the interfaces matched by this [`IfMatcher`].
+    pub if_match: Option<String>,
+}
+
+impl IpSelector {
+    pub fn is_empty(&self) -> bool {
+        self.ip_in.is_none() && self.ip_route_to.is_none() && self.if_match.is_none()
+    }
+
+    /// These rules, with the ones missing taken from `other`.
+    pub fn or(self, other: &Self) -> Self {
+        Self {
+            ip_in: self.ip_in.or(other.ip_in),
+            ip_route_to: self.ip_route_to.or_else(|| other.ip_route_to.clone()),
+            if_match: self.if_match.or_else(|| other.if_match.clone()),
+        }
+    }
+
+    /// The one address every rule agrees on, IPv4 wins over IPv6.
+    /// No match or more than one is an error, never a prompt.
+    pub fn select(&self) -> Result<IpAddr> {
+        let matcher: Option<IfMatcher> = self.if_match.as_deref().map(str::parse).transpose()?;
+        let route = match self.ip_route_to {
+            Some(ref host) => Some(route_source(host)?),
+            None => None,
+        };
+        let mut found: Vec<(String, IpAddr)> = Vec::new();
+        for iface in interfaces()? {
+            if iface.is_loopback() || matcher.as_ref().is_some_and(|m| !m.matches(&iface)) {
+                continue;
+            }
+            for addr in &iface.addrs {
+                let ip = addr.ip;
+                if ip.is_loopback()
+                    || matches!(ip, IpAddr::V6(v6) if v6.is_unicast_link_local())
+                    || self.ip_in.is_some_and(|net| !net.contains(&ip))
+                    || route.is_some_and(|route| route != ip)
+                {
+                    continue;
+                }
+                found.push((iface.name.clone(), ip));
+            }
+        }
+        if found.iter().any(|(_, ip)| ip.is_ipv4()) {
+            found.retain(|(_, ip)| ip.is_ipv4());
+        }
+        match found.as_slice() {
+            [(_, ip)] => Ok(*ip),
+            [] => Err(Box::new(SrunError::SelectIp(format!(
+                "no address matches {}",
+                self
+            )))),
+            _ => {
+                let found: Vec<String> = found
+                    .iter()
+                    .map(|(name, ip)| format!("{} ({})", ip, name))
+                    .collect();
+                Err(Box::new(SrunError::SelectIp(format!(
+                    "{} matches {}, narrow it down",
+                    self,
+                    found.join(", ")
+                ))))
+            }
+        }
+    }
+}
+
+impl fmt::Display for IpSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rules = Vec::new();
+        if let Some(net) = self.ip_in {
+            rules.push(format!("ip-in {}", net));
+        }
+        if let Some(ref host) = self.ip_route_to {
+            rules.push(format!("ip-route-to {}", host));
+        }
+        if let Some(ref pattern) = self.if_match {
+            rules.push(format!("if-match {}", pattern));
+        }
+        f.write_str(&rules.join(", "))
+    }
+}
+
+/// Source address of the route to `host`, which may be an auth server URL.
+/// Connecting a UDP socket sends nothing, it only looks up the route.
+pub fn route_source(host: &str) -> Result<IpAddr> {
+    let host = host
+        .split_once("://")
+        .map_or(host, |(_, rest)| rest)
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    let addr = match host.to_socket_addrs() {
+        Ok(mut addrs) => addrs.next(),
+        // no port given
+        Err(_) => (host.trim_matches(['[', ']']), 80)
+            .to_socket_addrs()?
+            .next(),
+    }
+    .ok_or_else(|| SrunError::InvalidValue("host", host.to_owned()))?;
+    let bind: SocketAddr = if addr.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.connect(addr)?;
+    Ok(socket.local_addr()?.ip())
+}
+
+#[test]
+fn test_if_matcher() {
+    let mut iface = Interface {
+        name: "eth1".to_owned(),
+        index: 0,
+        mac: Some("02:00:00:00:00:01".to_owned()),
+        addrs: Vec::new(),
+        up: true,
+        running: true,
+    };
+    let exact: IfMatcher = "eth1".parse().unwrap();
+    let re: IfMatcher = r"re:^eth\d$".parse().unwrap();
+    assert!(exact.matches(&iface));
+    assert!(re.matches(&iface));
+    iface.name = "eth10".to_owned();
+    assert!(!exact.matches(&iface));
+    assert!(!re.matches(&iface));
+    let glob: IfMatcher = "macvlan*".parse().unwrap();
+    assert!(!glob.matches(&iface));
+    iface.name = "macvlan12".to_owned();
+    assert!(glob.matches(&iface));
+    let mac: IfMatcher = "mac:02-00-00-00-00-01".parse().unwrap();
+    assert!(mac.matches(&iface));
+}
+
+#[test]
+fn test_cidr() {
+    let net: Cidr = "10.0.0.0/8".parse().unwrap();
+    assert!(net.contains(&"10.1.2.3".parse().unwrap()));
+    assert!(!net.contains(&"11.0.0.1".parse().unwrap()));
+    let all: Cidr = "0.0.0.0/0".parse().unwrap();
+    assert!(all.contains(&"1.2.3.4".parse().unwrap()));
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+}
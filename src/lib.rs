@@ -1,15 +1,41 @@
-pub use file::read_config_from_file;
+pub use connectivity::{Check, CheckRule};
+pub use device::DevicePreset;
+pub use failover::{FailoverGroup, group_users};
+pub use file::{Config, read_config_from_file};
+pub use interface::{Cidr, IfAddr, IfMatcher, Interface, IpSelector, interfaces, route_source};
+pub use multidial::{MacvlanMode, MultiDial};
+pub use netns::in_netns;
+pub use pool::{AccountPool, Leases};
+pub use rotation::{Quota, QuotaKind, Rotation};
 pub use srun::*;
-pub use user::User;
-pub use utils::{get_ip_by_if_name, select_ip};
-pub use xencode::param_i;
+pub use state::UserState;
+pub use user::{DEFAULT_USERNAME_TEMPLATE, Operator, User, UserFilter};
+pub use utils::{
+    UserAddr, create_private_dir, get_if_name, get_ip_by_if_name, get_ipv6_by_if_name,
+    lock_private_file, match_users, open_private_file, select_ip, state_home, user_addrs,
+};
+pub use watch::{AddrChange, AddrWatcher};
+pub use xencode::{EncodingProfile, PasswordMode, param_i, param_i_with};
 
+mod connectivity;
+mod device;
+mod failover;
 mod file;
-#[cfg(feature = "ureq")]
 mod http_client;
+mod interface;
+mod multidial;
+#[cfg(target_os = "linux")]
+mod netlink;
+mod netns;
+pub mod output;
+mod pool;
+mod rotation;
 mod srun;
+mod srun3k;
+mod state;
 mod user;
 mod utils;
+mod watch;
 mod xencode;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -1,11 +1,28 @@
-use std::{env, process};
+use std::{
+    env,
+    path::PathBuf,
+    process,
+    str::FromStr,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
 
 use getopts::{Matches, Options};
+use serde::Serialize;
 
-use srun::{SrunClient, User, get_ip_by_if_name, read_config_from_file, select_ip};
+use srun::{
+    AccountPool, AddrWatcher, Check, CheckRule, Config, DEFAULT_USERNAME_TEMPLATE, DevicePreset,
+    EncodingProfile, IpSelector, KickPolicy, Leases, LogoutMode, Operator, Protocol, Quota,
+    Rotation, Sessions, SrunClient, User, UserAddr, UserFilter, UserState, eoutln, get_if_name,
+    get_ip_by_if_name, get_ipv6_by_if_name, group_users, in_netns, is_account_error, outln, output,
+    read_config_from_file, select_ip, user_addrs,
+};
 
 fn print_usage(opts: Option<&Options>) {
-    let brief = "Usage: srun ACTION [options]\n\nActions: login | logout".to_string();
+    let brief =
+        "Usage: srun ACTION [options]\n\nActions: login | logout | status | probe | multidial"
+            .to_string();
     if let Some(opts) = opts {
         print!("{}", opts.usage(&brief));
     } else {
@@ -13,6 +30,717 @@ fn print_usage(opts: Option<&Options>) {
     }
 }
 
+// srun3k drops sessions that miss a few keep-alive rounds, secs
+const KEEP_ALIVE_INTERVAL: u64 = 60;
+
+// an IPv6 AUTH_SERVER_IP needs brackets in the url
+fn default_auth_server() -> String {
+    let ip = env!("AUTH_SERVER_IP");
+    if ip.contains(':') {
+        format!("http://[{}]", ip)
+    } else {
+        format!("http://{}", ip)
+    }
+}
+
+fn parse_opt<T: FromStr>(matches: &Matches, name: &str) -> Option<T>
+where
+    T::Err: std::fmt::Display,
+{
+    matches.opt_str(name).map(|v| match v.parse() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    })
+}
+
+fn username_template(matches: &Matches, config: &Option<String>) -> String {
+    matches
+        .opt_str("username-template")
+        .or_else(|| config.clone())
+        .unwrap_or_else(|| DEFAULT_USERNAME_TEMPLATE.to_string())
+}
+
+// ip of the interface, looked up inside its namespace
+fn if_ip(if_name: Option<String>, netns: Option<&str>) -> String {
+    let if_name = if_name.unwrap_or_default();
+    in_netns(netns, || get_ip_by_if_name(&if_name))
+        .unwrap_or_else(|e| {
+            eprintln!("netns error: {}", e);
+            None
+        })
+        .unwrap_or_default()
+}
+
+fn if_ipv6(if_name: Option<String>, netns: Option<&str>) -> String {
+    let Some(if_name) = if_name else {
+        return String::new();
+    };
+    in_netns(netns, || get_ipv6_by_if_name(&if_name))
+        .unwrap_or_else(|e| {
+            eprintln!("netns error: {}", e);
+            None
+        })
+        .unwrap_or_default()
+}
+
+// SO_BINDTODEVICE takes the name of the interface, not an if_name pattern
+fn bind_interface(if_name: Option<&str>, netns: Option<&str>) -> Option<String> {
+    let if_name = if_name?;
+    in_netns(netns, || get_if_name(if_name)).unwrap_or_else(|e| {
+        eprintln!("netns error: {}", e);
+        None
+    })
+}
+
+fn select_ip_in(netns: Option<&str>) -> String {
+    in_netns(netns, select_ip)
+        .unwrap_or_else(|e| {
+            eprintln!("netns error: {}", e);
+            None
+        })
+        .unwrap_or_default()
+}
+
+// --ip-in, --ip-route-to and --if-match win over the config
+fn ip_selector(matches: &Matches, config: &IpSelector, auth_server: &str) -> IpSelector {
+    let ip_route_to = if matches.opt_present("ip-route-to") {
+        Some(matches.opt_str("ip-route-to").unwrap_or_default())
+    } else {
+        config.ip_route_to.clone()
+    };
+    IpSelector {
+        ip_in: parse_opt(matches, "ip-in").or(config.ip_in),
+        ip_route_to: ip_route_to.map(|host| {
+            if host.is_empty() {
+                auth_server.to_owned()
+            } else {
+                host
+            }
+        }),
+        if_match: matches
+            .opt_str("if-match")
+            .or_else(|| config.if_match.clone()),
+    }
+}
+
+fn select_ip_by(selector: &IpSelector, netns: Option<&str>) -> Result<String, String> {
+    in_netns(netns, || selector.select().map_err(|e| e.to_string()))
+        .map_err(|e| e.to_string())
+        .and_then(|ip| ip)
+        .map(|ip| ip.to_string())
+}
+
+// CMD mode has nothing left to do without the ip
+fn selected_ip(selector: &IpSelector, netns: Option<&str>) -> String {
+    select_ip_by(selector, netns).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+// the selector of a config user, its own rules win over the config's
+fn user_selector(matches: &Matches, user: &User, config: &IpSelector, server: &str) -> IpSelector {
+    ip_selector(matches, &user.select.clone().or(config), server)
+}
+
+// a config user's ip: given, from its if_name, or picked by the selector
+fn config_user_ip(user: &User, selector: &IpSelector) -> Result<String, String> {
+    match (&user.ip, &user.if_name) {
+        (Some(ip), _) => Ok(ip.clone()),
+        (None, Some(_)) => Ok(if_ip(user.if_name.clone(), user.netns.as_deref())),
+        (None, None) if !selector.is_empty() => select_ip_by(selector, user.netns.as_deref()),
+        (None, None) => Ok(String::new()),
+    }
+}
+
+// --state-dir, or the config's, or the default
+fn state_dir(matches: &Matches, config: Option<&PathBuf>) -> PathBuf {
+    matches
+        .opt_str("state-dir")
+        .map(PathBuf::from)
+        .or_else(|| config.cloned())
+        .unwrap_or_else(UserState::default_dir)
+}
+
+// the state of a user is kept for every interface or ip it is dialed on
+fn state_key(user: &User) -> String {
+    UserState::key(user.label(), user.if_name.as_deref().or(user.ip.as_deref()))
+}
+
+// users of the config picked by --user, --tag and --exclude, --netns and
+// --fwmark are theirs unless they have their own
+fn config_users(matches: &Matches, config: Config) -> Vec<User> {
+    let filter = UserFilter {
+        users: matches.opt_strs("user"),
+        tags: matches.opt_strs("tag"),
+        exclude: matches.opt_strs("exclude"),
+    };
+    let netns = matches.opt_str("netns");
+    let fwmark = parse_opt(matches, "fwmark");
+    let users: Vec<User> = config
+        .filter(|user| filter.matches(user))
+        .map(|mut user| {
+            user.netns = user.netns.or_else(|| netns.clone());
+            user.fwmark = user.fwmark.or(fwmark);
+            user
+        })
+        .collect();
+    if users.is_empty() {
+        eprintln!("no user of the config selected");
+        process::exit(1);
+    }
+    users
+}
+
+// one user per local address it matches, bound to that address
+fn all_interface_users(users: Vec<User>) -> Vec<(Option<UserAddr>, User)> {
+    let addrs = match user_addrs(&users) {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            eprintln!("list interfaces error: {}", e);
+            process::exit(1);
+        }
+    };
+    if addrs.is_empty() {
+        eprintln!("no local address matches a user");
+        process::exit(1);
+    }
+    addrs
+        .into_iter()
+        .map(|addr| {
+            let mut user = users[addr.user].clone();
+            user.ip = Some(addr.ip.to_string());
+            user.if_name = Some(addr.if_name.clone());
+            (Some(addr), user)
+        })
+        .collect()
+}
+
+// run `f` on up to `jobs` threads, results keep the order of `items`
+fn run_jobs<T: Send, R: Send>(items: Vec<T>, jobs: usize, f: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let len = items.len();
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new((0..len).map(|_| None).collect::<Vec<_>>());
+    thread::scope(|s| {
+        for _ in 0..jobs.clamp(1, len.max(1)) {
+            s.spawn(|| {
+                loop {
+                    let Some((i, item)) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    let result = f(item);
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(Option::unwrap)
+        .collect()
+}
+
+// how one user of a config did, a row of the summary
+#[derive(Serialize)]
+struct Outcome {
+    user: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interface: Option<String>,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sessions: Option<Sessions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Accounts of a failover group refused before this one.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed_over: Vec<String>,
+}
+
+impl Outcome {
+    fn new(user: String, addr: Option<UserAddr>, result: Result<Option<Sessions>, String>) -> Self {
+        Self {
+            user,
+            address: addr.as_ref().map(|addr| addr.ip.to_string()),
+            interface: addr.map(|addr| addr.if_name),
+            ok: result.is_ok(),
+            sessions: result.clone().ok().flatten(),
+            error: result.err(),
+            failed_over: Vec::new(),
+        }
+    }
+
+    fn result(&self) -> String {
+        let result = match (&self.error, &self.sessions) {
+            (Some(e), _) => format!("error: {}", e),
+            (None, Some(sessions)) => format!("ok, {}", sessions),
+            (None, None) => "ok".to_string(),
+        };
+        if self.failed_over.is_empty() {
+            result
+        } else {
+            format!("{} (after {})", result, self.failed_over.join(", "))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum SummaryFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+impl FromStr for SummaryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("invalid summary format: {}", s)),
+        }
+    }
+}
+
+// json goes on a single line, so it can be picked from the end of the output
+fn print_summary(outcomes: &[Outcome], format: SummaryFormat) {
+    if format == SummaryFormat::Json {
+        match serde_json::to_string(outcomes) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("summary error: {}", e),
+        }
+        return;
+    }
+    let with_addr = outcomes.iter().any(|o| o.address.is_some());
+    let row = |cells: [&str; 4]| -> Vec<String> {
+        let cells = if with_addr {
+            cells.to_vec()
+        } else {
+            vec![cells[0], cells[3]]
+        };
+        cells.into_iter().map(str::to_owned).collect()
+    };
+    let mut rows = vec![row(["USER", "ADDRESS", "INTERFACE", "RESULT"])];
+    for o in outcomes {
+        rows.push(row([
+            &o.user,
+            o.address.as_deref().unwrap_or_default(),
+            o.interface.as_deref().unwrap_or_default(),
+            &o.result(),
+        ]));
+    }
+    let mut widths = vec![0; rows[0].len()];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in rows {
+        let last = row.len() - 1;
+        let line: Vec<String> = row
+            .into_iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if i == last {
+                    cell
+                } else {
+                    format!("{:<1$}", cell, widths[i])
+                }
+            })
+            .collect();
+        println!("{}", line.join("  "));
+    }
+}
+
+fn exit_code(outcomes: &[Outcome]) -> i32 {
+    failure_code(outcomes.iter().filter(|o| !o.ok).count(), outcomes.len())
+}
+
+// 0 if every user is ok, 1 if all failed, 2 if only some did
+fn failure_code(failed: usize, total: usize) -> i32 {
+    match failed {
+        0 => 0,
+        failed if failed == total => 1,
+        _ => 2,
+    }
+}
+
+fn checks(matches: &Matches, config: &Config) -> (Vec<Check>, CheckRule) {
+    let mut checks: Vec<Check> = matches
+        .opt_strs("check")
+        .iter()
+        .map(|c| match c.parse() {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        })
+        .collect();
+    if checks.is_empty() {
+        checks = config.checks.clone();
+    }
+    let rule = parse_opt(matches, "check-rule").unwrap_or(config.check_rule);
+    (checks, rule)
+}
+
+// the accounts that can take one address, `current` is the one logged in
+struct Dial {
+    accounts: Vec<(String, SrunClient)>,
+    current: usize,
+    // last known quota of every account, refused ones count as empty
+    remain: Vec<Option<f64>>,
+    rotated: Option<Instant>,
+    watched: Option<String>,
+    // the pool and interface of a dial leasing its account
+    lease: Option<(AccountPool, String)>,
+}
+
+impl Dial {
+    fn new(
+        accounts: Vec<(String, SrunClient, Option<String>)>,
+        current: usize,
+        refused: &[String],
+        rotation: Option<&Rotation>,
+    ) -> Self {
+        let watched = accounts[current].2.clone();
+        let accounts: Vec<(String, SrunClient)> = accounts
+            .into_iter()
+            .map(|(username, client, _)| (username, client))
+            .collect();
+        let mut remain: Vec<Option<f64>> = accounts
+            .iter()
+            .map(|(username, _)| refused.contains(username).then_some(0.0))
+            .collect();
+        if let Some(rotation) = rotation {
+            remain[current] = accounts[current].1.quota().ok().map(|q| rotation.by.of(&q));
+        }
+        Self {
+            accounts,
+            current,
+            remain,
+            rotated: None,
+            watched,
+            lease: None,
+        }
+    }
+
+    fn usernames(&self) -> Vec<String> {
+        self.accounts
+            .iter()
+            .map(|(username, _)| username.clone())
+            .collect()
+    }
+
+    fn client(&mut self) -> &mut SrunClient {
+        &mut self.accounts[self.current].1
+    }
+}
+
+// move the dial to the account with the most quota left once the current one
+// runs low, and back to the old one if the portal refuses the new one
+fn rotate(dial: &mut Dial, rotation: &Rotation) {
+    if dial.accounts.len() < 2 {
+        return;
+    }
+    if let Some(rotated) = dial.rotated
+        && rotated.elapsed() < Duration::from_secs(rotation.min_interval)
+    {
+        return;
+    }
+    let current = dial.current;
+    let (ref username, ref client) = dial.accounts[current];
+    match client.user_info() {
+        Ok(info) if !info.online_ip.is_empty() => {
+            dial.remain[current] = Some(rotation.by.of(&Quota::from(&info)));
+        }
+        Ok(_) => return,
+        Err(e) => {
+            eoutln!("{}: user info error: {}", username, e);
+            return;
+        }
+    }
+    let next = match dial.lease {
+        Some((ref pool, ref if_name)) => {
+            let usernames = dial.usernames();
+            with_leases(pool, |leases| {
+                // accounts other interfaces hold are out of reach
+                let remain: Vec<Option<f64>> = dial
+                    .remain
+                    .iter()
+                    .zip(&usernames)
+                    .map(|(remain, username)| match leases.holder(username) {
+                        Some(holder) if holder != if_name => Some(f64::NEG_INFINITY),
+                        _ => *remain,
+                    })
+                    .collect();
+                let next = rotation.pick(&remain, current)?;
+                leases.take(if_name, &usernames[next]).then_some(next)
+            })
+            .flatten()
+        }
+        None => rotation.pick(&dial.remain, current),
+    };
+    let Some(next) = next else {
+        return;
+    };
+    outln!(
+        "{} has {} left, rotating to {}",
+        username,
+        dial.remain[current].unwrap_or_default(),
+        dial.accounts[next].0
+    );
+    dial.rotated = Some(Instant::now());
+    let client = &mut dial.accounts[current].1;
+    if let Err(e) = client.logout() {
+        eoutln!("logout error: {}", e);
+        return;
+    }
+    let (ip, ipv6) = (client.ip().to_owned(), client.ipv6().to_owned());
+    let (ref username, ref mut client) = dial.accounts[next];
+    client.set_ip(&ip);
+    client.set_ipv6(&ipv6);
+    match client.login() {
+        Ok(_) => {
+            dial.remain[next] = client.quota().ok().map(|q| rotation.by.of(&q));
+            dial.current = next;
+        }
+        Err(e) => {
+            let username = username.clone();
+            eoutln!("{}: {}, back to {}", username, e, dial.accounts[current].0);
+            if is_account_error(e.as_ref()) {
+                dial.remain[next] = Some(0.0);
+            }
+            if let Some((ref pool, ref if_name)) = dial.lease {
+                let username = &dial.accounts[current].0;
+                with_leases(pool, |leases| leases.take(if_name, username));
+            }
+            if let Err(e) = dial.client().login() {
+                eoutln!("login error: {}", e);
+            }
+        }
+    }
+}
+
+// keep sessions alive and login again whenever the checks fail, or the
+// address of a watched interface changes
+fn keep_alive(dials: &mut [Dial], interval: Option<u64>, rotation: Option<&Rotation>) {
+    let mut watcher = if dials.iter().any(|dial| dial.watched.is_some()) {
+        match AddrWatcher::new() {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                eprintln!("watch error: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    if dials.is_empty() || (interval.is_none() && watcher.is_none()) {
+        return;
+    }
+    let interval = interval.map(Duration::from_secs);
+    let mut last = Instant::now();
+    loop {
+        let wait = interval.map(|i| i.saturating_sub(last.elapsed()));
+        match watcher {
+            Some(ref mut watcher) => match watcher.next(wait) {
+                // if_name may be a pattern, so every watched user is checked
+                Ok(changes) if !changes.is_empty() => {
+                    for dial in dials.iter_mut() {
+                        match dial.watched.clone() {
+                            Some(if_name) if dial.lease.is_some() => follow_lease(dial, &if_name),
+                            Some(if_name) => follow_addr(dial.client(), &if_name),
+                            None => {}
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("watch error: {}", e);
+                    thread::sleep(Duration::from_secs(1));
+                }
+            },
+            None => thread::sleep(wait.unwrap_or_default()),
+        }
+
+        let Some(interval) = interval else {
+            continue;
+        };
+        if last.elapsed() < interval {
+            continue;
+        }
+        last = Instant::now();
+        for dial in dials.iter_mut() {
+            if let Some(rotation) = rotation {
+                rotate(dial, rotation);
+            }
+            let client = dial.client();
+            if let Err(e) = client.keep_alive() {
+                eprintln!("keep alive error: {}", e);
+            }
+            if client.check_online() {
+                continue;
+            }
+            println!("network offline, login again");
+            if let Err(e) = client.login() {
+                eprintln!("login error: {}", e);
+            }
+        }
+    }
+}
+
+// re-resolve the interface's address, move the session over if it changed
+fn follow_addr(client: &mut SrunClient, if_name: &str) {
+    let ip = if_ip(Some(if_name.to_owned()), None);
+    let ipv6 = if_ipv6(Some(if_name.to_owned()), None);
+    if ip == client.ip() && ipv6 == client.ipv6() {
+        return;
+    }
+    println!(
+        "{} address changed: {} {} -> {} {}",
+        if_name,
+        client.ip(),
+        client.ipv6(),
+        ip,
+        ipv6
+    );
+    // the old address is usually gone already, so this is best effort
+    if !client.ip().is_empty()
+        && let Err(e) = client.logout()
+    {
+        eprintln!("logout error: {}", e);
+    }
+    client.set_ip(&ip);
+    client.set_ipv6(&ipv6);
+    if ip.is_empty() {
+        return;
+    }
+    // a pattern may match another interface by now
+    if client.bind_interface().is_some() {
+        *client = client
+            .clone()
+            .set_bind_interface(bind_interface(Some(if_name), None));
+    }
+    if let Err(e) = client.login() {
+        eprintln!("login error: {}", e);
+    }
+}
+
+// run `f` on the locked leases of the pool and save them
+fn with_leases<R>(pool: &AccountPool, f: impl FnOnce(&mut Leases) -> R) -> Option<R> {
+    let mut leases = match pool.lock() {
+        Ok(leases) => leases,
+        Err(e) => {
+            eoutln!("lease file error: {}", e);
+            return None;
+        }
+    };
+    let result = f(&mut leases);
+    if let Err(e) = leases.save() {
+        eoutln!("lease file error: {}", e);
+        return None;
+    }
+    Some(result)
+}
+
+// lease the account `if_name` already holds, or else the first free one that
+// was not tried yet; the old lease is given up if there is none
+fn lease(
+    leases: &mut Leases,
+    if_name: &str,
+    usernames: &[String],
+    tried: &[bool],
+) -> Option<usize> {
+    let held = leases
+        .get(if_name)
+        .and_then(|held| usernames.iter().position(|username| username == held))
+        .filter(|&i| !tried[i]);
+    let free =
+        || (0..usernames.len()).find(|&i| !tried[i] && leases.holder(&usernames[i]).is_none());
+    match held.or_else(free) {
+        Some(i) => {
+            leases.take(if_name, &usernames[i]);
+            Some(i)
+        }
+        None => {
+            leases.release(if_name);
+            None
+        }
+    }
+}
+
+// like follow_addr, but the lease is given up when the interface loses its
+// address, and taken again before logging in once it is back
+fn follow_lease(dial: &mut Dial, if_name: &str) {
+    let (pool, _) = dial.lease.clone().expect("a dial with a lease");
+    if !dial.client().ip().is_empty() {
+        follow_addr(dial.client(), if_name);
+        if dial.client().ip().is_empty() {
+            with_leases(&pool, |leases| leases.release(if_name));
+            outln!("{} released", if_name);
+        }
+        return;
+    }
+    let ip = if_ip(Some(if_name.to_owned()), None);
+    if ip.is_empty() {
+        return;
+    }
+    // the account used before goes first if it is still free
+    let usernames = dial.usernames();
+    let current = dial.current;
+    let next = with_leases(&pool, |leases| {
+        if leases.take(if_name, &usernames[current]) {
+            Some(current)
+        } else {
+            lease(leases, if_name, &usernames, &vec![false; usernames.len()])
+        }
+    });
+    let Some(Some(next)) = next else {
+        eoutln!("{}: no free account in the pool", if_name);
+        return;
+    };
+    dial.current = next;
+    outln!(
+        "{} address is back: {}, login {}",
+        if_name,
+        ip,
+        usernames[next]
+    );
+    let ipv6 = if_ipv6(Some(if_name.to_owned()), None);
+    let client = dial.client();
+    client.set_ip(&ip);
+    client.set_ipv6(&ipv6);
+    if let Err(e) = client.login() {
+        eoutln!("login error: {}", e);
+        with_leases(&pool, |leases| leases.release(if_name));
+    }
+}
+
+fn detect_encoding(auth_server: &str) -> Option<EncodingProfile> {
+    match SrunClient::new_for_probe(auth_server).detect_encoding() {
+        Ok(Some(encoding)) => {
+            println!("detected encoding: {:#?}", encoding);
+            Some(encoding)
+        }
+        Ok(None) => {
+            println!("no encoding profile found on portal page, use default");
+            None
+        }
+        Err(e) => {
+            eprintln!("detect encoding error: {}", e);
+            None
+        }
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
@@ -23,36 +751,178 @@ fn main() {
     match args[1].as_str() {
         "login" => login_match(&args),
         "logout" => logout_match(&args),
+        "status" => status_match(&args),
+        "probe" => probe_match(&args),
+        "multidial" => multidial_match(&args),
         _ => {
             print_usage(None);
         }
     }
 }
 
+fn login_options() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print help message");
+    opts.optopt("s", "server", "auth server", "");
+    opts.optopt("c", "config", "config file path", "");
+    opts.optflag(
+        "",
+        "continue",
+        "keep running, send keep-alive and login again when offline",
+    );
+    opts.optopt(
+        "",
+        "interval",
+        "check interval of --continue, default 60 secs",
+        "",
+    );
+    opts.optflag(
+        "",
+        "watch",
+        "keep running, login again when the address of an if_name changes",
+    );
+    opts.optopt("u", "username", "username", "");
+    opts.optopt("p", "password", "password", "");
+    opts.optopt("i", "ip", "ip", "");
+    opts.optflag("d", "detect", "detect client ip");
+    opts.optflag("", "select-ip", "select client ip");
+    opts.optopt(
+        "",
+        "ip-in",
+        "use the local ip inside this network, e.g. 10.0.0.0/8",
+        "",
+    );
+    opts.optflagopt(
+        "",
+        "ip-route-to",
+        "use the local ip routed to this host, default the auth server",
+        "",
+    );
+    opts.optopt(
+        "",
+        "if-match",
+        "use the ip of the interface matching this pattern",
+        "",
+    );
+    opts.optopt(
+        "j",
+        "jobs",
+        "login users of the config in parallel, default 1",
+        "",
+    );
+    opts.optmulti(
+        "",
+        "user",
+        "only this user of the config, by name or username",
+        "",
+    );
+    opts.optmulti("", "tag", "only users of the config with this tag", "");
+    opts.optmulti(
+        "",
+        "exclude",
+        "leave out users of the config with this name or tag",
+        "",
+    );
+    opts.optopt(
+        "",
+        "summary",
+        "summary of a config login: table | json, default table",
+        "",
+    );
+    opts.optflag(
+        "",
+        "all-interfaces",
+        "login every local address that matches a config user by if_name or subnet",
+    );
+    opts.optflag("", "strict-bind", "strict bind ip");
+    opts.optopt(
+        "",
+        "bind-interface",
+        "send requests out of this interface",
+        "",
+    );
+    opts.optopt(
+        "",
+        "fwmark",
+        "set SO_MARK on sockets, for policy routing",
+        "",
+    );
+    opts.optopt("", "netns", "run inside this named network namespace", "");
+    opts.optopt(
+        "",
+        "state-dir",
+        "keep the state of users in this directory",
+        "",
+    );
+    opts.optflag("", "test", "test network connection before login");
+    opts.optmulti(
+        "",
+        "check",
+        "connectivity check: tcp:HOST:PORT | http:URL | dns:HOST | portal",
+        "",
+    );
+    opts.optopt(
+        "",
+        "check-rule",
+        "combine checks: any | all, default any",
+        "",
+    );
+    opts.optflag("", "double-stack", "enable double stack");
+    opts.optopt("", "ipv6", "ipv6 of the double stack session", "");
+    opts.optopt(
+        "",
+        "server6",
+        "auth server of the ipv6 session, default the auth server",
+        "",
+    );
+    opts.optopt("n", "param-n", "n", "");
+    opts.optopt("", "type", "type", "");
+    opts.optopt("", "acid", "acid", "");
+    opts.optopt("", "os", "os, e.g. Windows", "");
+    opts.optopt("", "name", "name, e.g. Windows 98", "");
+    opts.optopt(
+        "",
+        "device",
+        "device preset: pc-windows | linux | macos | android | ios",
+        "",
+    );
+    opts.optopt(
+        "",
+        "operator",
+        "network operator: ctcc | cmcc | unicom | xn",
+        "",
+    );
+    opts.optopt(
+        "",
+        "username-template",
+        "how to append operator, default {username}@{operator}",
+        "",
+    );
+    opts.optopt("", "retry-delay", "retry delay, default 300 millis", "");
+    opts.optopt("", "retry-times", "retry times, default 10 times", "");
+    opts.optflag("", "kick", "kick other devices when device limit reached");
+    opts.optopt(
+        "",
+        "kick-policy",
+        "which devices to kick: oldest | newest | all, default oldest",
+        "",
+    );
+    opts.optopt(
+        "",
+        "protocol",
+        "portal protocol: srun | srun3k, default srun",
+        "",
+    );
+    opts.optflag(
+        "",
+        "detect-encoding",
+        "detect the encoding profile from the portal page",
+    );
+    opts
+}
+
 fn login_match(args: &[String]) {
-    let options = {
-        let mut opts = Options::new();
-        opts.optflag("h", "help", "print help message");
-        opts.optopt("s", "server", "auth server", "");
-        opts.optopt("c", "config", "config file path", "");
-        opts.optflag("", "continue", "[Unimplemented] continuous login");
-        opts.optopt("u", "username", "username", "");
-        opts.optopt("p", "password", "password", "");
-        opts.optopt("i", "ip", "ip", "");
-        opts.optflag("d", "detect", "detect client ip");
-        opts.optflag("", "select-ip", "select client ip");
-        opts.optflag("", "strict-bind", "strict bind ip");
-        opts.optflag("", "test", "test network connection before login");
-        opts.optflag("", "double-stack", "enable double stack");
-        opts.optopt("n", "param-n", "n", "");
-        opts.optopt("", "type", "type", "");
-        opts.optopt("", "acid", "acid", "");
-        opts.optopt("", "os", "os, e.g. Windows", "");
-        opts.optopt("", "name", "name, e.g. Windows 98", "");
-        opts.optopt("", "retry-delay", "retry delay, default 300 millis", "");
-        opts.optopt("", "retry-times", "retry times, default 10 times", "");
-        opts
-    };
+    let options = login_options();
 
     let matches = match options.parse(args) {
         Ok(m) => m,
@@ -65,26 +935,128 @@ fn login_match(args: &[String]) {
     if matches.opt_present("h") {
         print_usage(Some(&options));
     } else if matches.opt_present("c") {
-        config_login(matches);
+        config_login(matches, &[]);
+    } else if matches.opt_present("all-interfaces") {
+        println!("need config for --all-interfaces, use '-c CONFIG'");
+    } else if matches.opt_present("watch") {
+        println!("need config for --watch, use '-c CONFIG'");
     } else {
         single_login(matches);
     }
 }
 
+fn logout_options() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print help message");
+    opts.optopt("s", "server", "auth server", "");
+    opts.optopt("u", "username", "username", "");
+    opts.optopt("i", "ip", "ip", "");
+    opts.optflag("d", "detect", "detect client ip");
+    opts.optopt("c", "config", "logout by config file", "");
+    opts.optopt(
+        "j",
+        "jobs",
+        "logout users of the config in parallel, default 1",
+        "",
+    );
+    opts.optmulti(
+        "",
+        "user",
+        "only this user of the config, by name or username",
+        "",
+    );
+    opts.optmulti("", "tag", "only users of the config with this tag", "");
+    opts.optmulti(
+        "",
+        "exclude",
+        "leave out users of the config with this name or tag",
+        "",
+    );
+    opts.optopt(
+        "",
+        "summary",
+        "summary of a config logout: table | json, default table",
+        "",
+    );
+    opts.optflag("", "select-ip", "select client ip");
+    opts.optopt(
+        "",
+        "ip-in",
+        "use the local ip inside this network, e.g. 10.0.0.0/8",
+        "",
+    );
+    opts.optflagopt(
+        "",
+        "ip-route-to",
+        "use the local ip routed to this host, default the auth server",
+        "",
+    );
+    opts.optopt(
+        "",
+        "if-match",
+        "use the ip of the interface matching this pattern",
+        "",
+    );
+    opts.optflag("", "strict-bind", "strict bind ip");
+    opts.optopt(
+        "",
+        "bind-interface",
+        "send requests out of this interface",
+        "",
+    );
+    opts.optopt(
+        "",
+        "fwmark",
+        "set SO_MARK on sockets, for policy routing",
+        "",
+    );
+    opts.optopt("", "netns", "run inside this named network namespace", "");
+    opts.optopt(
+        "",
+        "state-dir",
+        "keep the state of users in this directory",
+        "",
+    );
+    opts.optflag("", "double-stack", "also logout the ipv6 session");
+    opts.optopt("", "ipv6", "ipv6 of the double stack session", "");
+    opts.optopt(
+        "",
+        "server6",
+        "auth server of the ipv6 session, default the auth server",
+        "",
+    );
+    opts.optopt("", "acid", "acid", "");
+    opts.optopt(
+        "",
+        "mode",
+        "logout mode: auto | legacy | signed | unbind, default auto",
+        "",
+    );
+    opts.optflag("", "all", "logout all sessions of the user, no ip needed");
+    opts.optopt("p", "password", "password, needed by srun3k logout", "");
+    opts.optopt(
+        "",
+        "operator",
+        "network operator: ctcc | cmcc | unicom | xn",
+        "",
+    );
+    opts.optopt(
+        "",
+        "username-template",
+        "how to append operator, default {username}@{operator}",
+        "",
+    );
+    opts.optopt(
+        "",
+        "protocol",
+        "portal protocol: srun | srun3k, default srun",
+        "",
+    );
+    opts
+}
+
 fn logout_match(args: &[String]) {
-    let options = {
-        let mut opts = Options::new();
-        opts.optflag("h", "help", "print help message");
-        opts.optopt("s", "server", "auth server", "");
-        opts.optopt("u", "username", "username", "");
-        opts.optopt("i", "ip", "ip", "");
-        opts.optflag("d", "detect", "detect client ip");
-        opts.optopt("c", "config", "logout by config file", "");
-        opts.optflag("", "select-ip", "select client ip");
-        opts.optflag("", "strict-bind", "strict bind ip");
-        opts.optopt("", "acid", "acid", "");
-        opts
-    };
+    let options = logout_options();
 
     let matches = match options.parse(args) {
         Ok(m) => m,
@@ -97,62 +1069,275 @@ fn logout_match(args: &[String]) {
     if matches.opt_present("h") {
         print_usage(Some(&options));
     } else if matches.opt_present("c") {
-        config_logout(matches);
+        let code = config_logout(matches, &[]);
+        if code != 0 {
+            process::exit(code);
+        }
     } else {
         logout(matches)
     }
 }
 
-fn config_login(matches: Matches) {
+// users without if_name get one of `if_names`
+fn config_login(matches: Matches, if_names: &[String]) {
     let config_path = matches.opt_str("c").unwrap();
     match read_config_from_file(config_path) {
-        Ok(config) => {
+        Ok(mut config) => {
+            config.assign_if_names(if_names);
             let config_i = config.clone();
             let server = config
                 .server
                 .clone()
                 .unwrap_or_else(|| match matches.opt_str("s") {
                     Some(u) => u,
-                    None => format!("http://{}", env!("AUTH_SERVER_IP")),
+                    None => default_auth_server(),
                 });
-            for mut user in config_i {
-                println!("login user: {:#?}", user);
-                let mut detect_ip = false;
-                if user.ip.is_none() && user.if_name.is_none() {
-                    println!("miss both ip and if_name, will auto detect ip");
-                    user.ip = Some(String::new());
-                    detect_ip = true
-                }
-                let mut client = SrunClient::new_from_user(&server, user)
-                    .set_detect_ip(detect_ip || config.detect_ip)
-                    .set_strict_bind(config.strict_bind)
-                    .set_double_stack(config.double_stack);
-                if let Some(n) = config.n {
-                    client.set_n(n);
-                }
-                if let Some(utype) = config.utype {
-                    client.set_type(utype);
-                }
-                if let Some(acid) = config.acid {
-                    client.set_acid(acid);
-                }
-                if let Some(ref os) = config.os {
-                    client.set_os(os);
-                }
-                if let Some(ref name) = config.name {
-                    client.set_name(name);
+            let server6 = config
+                .server6
+                .clone()
+                .or_else(|| matches.opt_str("server6"));
+            let protocol = parse_opt(&matches, "protocol").or(config.protocol);
+            let template = username_template(&matches, &config.username_template);
+            let encoding = if config.detect_encoding || matches.opt_present("detect-encoding") {
+                detect_encoding(&server).or(config.encoding.clone())
+            } else {
+                config.encoding.clone()
+            };
+            let (checks, check_rule) = checks(&matches, &config);
+            let test = config.test || matches.opt_present("test");
+            let watch = config.watch || matches.opt_present("watch");
+            let all_interfaces = matches.opt_present("all-interfaces");
+            let state_dir = state_dir(&matches, config.state_dir.as_ref());
+            let users = config_users(&matches, config_i);
+            // leases of an srun that keeps running are stale once it is gone
+            let keep_running = watch || matches.opt_present("continue");
+            let pool = config
+                .pool
+                .clone()
+                .filter(|_| !all_interfaces)
+                .map(|pool| pool.set_pid(keep_running.then(process::id)));
+            let pool = pool.as_ref();
+            let (members, users) = match pool {
+                Some(pool) => pool.split(users),
+                None => (Vec::new(), users),
+            };
+            let mut dials: Vec<(Option<UserAddr>, Option<String>, Vec<User>)> = if all_interfaces {
+                all_interface_users(users)
+                    .into_iter()
+                    .map(|(addr, user)| (addr, None, vec![user]))
+                    .collect()
+            } else {
+                group_users(users, &config.failover)
+                    .into_iter()
+                    .map(|dial| (None, None, dial))
+                    .collect()
+            };
+            // every pool interface may take any of the members
+            if let Some(pool) = pool
+                && !members.is_empty()
+            {
+                for if_name in &pool.if_names {
+                    let dial = members
+                        .iter()
+                        .cloned()
+                        .map(|mut user| {
+                            user.if_name = Some(if_name.clone());
+                            user.ip = None;
+                            user
+                        })
+                        .collect();
+                    dials.push((None, Some(if_name.clone()), dial));
                 }
-                if let Some(retry_delay) = config.retry_delay {
-                    client.set_retry_delay(retry_delay);
+            }
+            let mut logins = Vec::new();
+            // users without an ip to log in
+            let mut unselected = Vec::new();
+            for (addr, leased, dial) in dials {
+                let mut accounts = Vec::new();
+                for mut user in dial {
+                    println!("login user: {:#?}", user);
+                    let username = user.label().to_owned();
+                    let state_key = state_key(&user);
+                    let mut detect_ip = false;
+                    let selector = user_selector(&matches, &user, &config.select, &server);
+                    if user.ip.is_none() && user.if_name.is_none() && !selector.is_empty() {
+                        match select_ip_by(&selector, user.netns.as_deref()) {
+                            Ok(ip) => user.ip = Some(ip),
+                            Err(e) => {
+                                eprintln!("{}: {}", username, e);
+                                unselected.push(Outcome::new(username, addr.clone(), Err(e)));
+                                continue;
+                            }
+                        }
+                    }
+                    if user.ip.is_none() && user.if_name.is_none() {
+                        println!("miss both ip and if_name, will auto detect ip");
+                        user.ip = Some(String::new());
+                        detect_ip = true
+                    }
+                    let device = user.device;
+                    // interfaces in other namespaces can not be watched from here
+                    // a lease has to be given up when its interface goes away
+                    let watch = watch || (leased.is_some() && keep_running);
+                    let watched = user
+                        .if_name
+                        .clone()
+                        .filter(|_| watch && user.ip.is_none() && user.netns.is_none());
+                    let kick = (config.kick || matches.opt_present("kick")).then(|| {
+                        parse_opt(&matches, "kick-policy")
+                            .or(config.kick_policy)
+                            .unwrap_or_default()
+                    });
+                    let bind_interface = bind_interface(
+                        user.if_name.as_deref().filter(|_| config.bind_interface),
+                        user.netns.as_deref(),
+                    )
+                    .or_else(|| matches.opt_str("bind-interface"));
+                    let mut client =
+                        SrunClient::new_from_user_with_template(&server, user, &template)
+                            .set_detect_ip(detect_ip || config.detect_ip)
+                            .set_test_before_login(test)
+                            .set_strict_bind(config.strict_bind || all_interfaces)
+                            .set_bind_interface(bind_interface)
+                            .set_double_stack(
+                                config.double_stack || matches.opt_present("double-stack"),
+                            )
+                            .set_auth_server6(server6.clone())
+                            .set_kick(kick)
+                            .set_state_dir(Some(state_dir.clone()))
+                            .set_state_key(state_key);
+                    if let Some(device) = config.device {
+                        client.set_device(device);
+                    }
+                    if let Some(n) = config.n {
+                        client.set_n(n);
+                    }
+                    if let Some(utype) = config.utype {
+                        client.set_type(utype);
+                    }
+                    if let Some(acid) = config.acid {
+                        client.set_acid(acid);
+                    }
+                    if let Some(ref os) = config.os {
+                        client.set_os(os);
+                    }
+                    if let Some(ref name) = config.name {
+                        client.set_name(name);
+                    }
+                    // a per user preset wins over the shared fingerprint
+                    if let Some(device) = device {
+                        client.set_device(device);
+                    }
+                    if let Some(retry_delay) = config.retry_delay {
+                        client.set_retry_delay(retry_delay);
+                    }
+                    if let Some(retry_times) = config.retry_times {
+                        client.set_retry_times(retry_times);
+                    }
+                    if let Some(ref online_path) = config.online_path {
+                        client.set_online_path(online_path);
+                    }
+                    if let Some(protocol) = protocol {
+                        client.set_protocol(protocol);
+                    }
+                    if let Some(ref encoding) = encoding {
+                        client.set_encoding(encoding.clone());
+                    }
+                    client.set_checks(checks.clone(), check_rule);
+                    accounts.push((username, client, watched));
                 }
-                if let Some(retry_times) = config.retry_times {
-                    client.set_retry_times(retry_times);
+                if accounts.is_empty() {
+                    continue;
                 }
+                let label = match (&addr, &leased) {
+                    (Some(addr), _) => format!("{} {}", accounts[0].0, addr.ip),
+                    (None, Some(if_name)) => if_name.clone(),
+                    (None, None) => accounts[0].0.clone(),
+                };
+                logins.push((label, addr, leased, accounts));
+            }
 
-                if let Err(e) = client.login() {
-                    eprintln!("login error: {}", e);
-                    process::exit(1);
+            let jobs = parse_opt(&matches, "jobs").or(config.jobs).unwrap_or(1);
+            let results = run_jobs(logins, jobs, |(label, addr, leased, mut accounts)| {
+                if jobs > 1 {
+                    output::set_prefix(&format!("[{}] ", label));
+                }
+                let usernames: Vec<String> = accounts.iter().map(|a| a.0.clone()).collect();
+                let mut tried = vec![false; accounts.len()];
+                // a pool dial leases each account before trying it
+                let next = |tried: &[bool]| match (pool, &leased) {
+                    (Some(pool), Some(if_name)) => {
+                        with_leases(pool, |leases| lease(leases, if_name, &usernames, tried))
+                            .flatten()
+                    }
+                    _ => tried.iter().position(|tried| !tried),
+                };
+                let mut failed_over = Vec::new();
+                let Some(mut current) = next(&tried) else {
+                    let e = "no free account in the pool".to_owned();
+                    eoutln!("login error: {}", e);
+                    return (addr, leased, accounts, 0, Err(e), failed_over);
+                };
+                let result = loop {
+                    tried[current] = true;
+                    let (ref username, ref mut client, _) = accounts[current];
+                    let e = match client.login() {
+                        Ok(sessions) => {
+                            if !failed_over.is_empty() {
+                                outln!("failed over to {}", username);
+                            }
+                            break Ok(sessions);
+                        }
+                        Err(e) => e,
+                    };
+                    // only the account is refused, the next one may get in
+                    if is_account_error(e.as_ref())
+                        && let Some(i) = next(&tried)
+                    {
+                        eoutln!("{}: {}, trying the next account", username, e);
+                        failed_over.push(username.clone());
+                        current = i;
+                        continue;
+                    }
+                    let e = e.to_string();
+                    eoutln!("login error: {}", e);
+                    if let (Some(pool), Some(if_name)) = (pool, &leased) {
+                        with_leases(pool, |leases| leases.release(if_name));
+                    }
+                    break Err(e);
+                };
+                (addr, leased, accounts, current, result, failed_over)
+            });
+            let mut alive = Vec::new();
+            let mut outcomes = unselected;
+            for (addr, leased, accounts, current, result, failed_over) in results {
+                let username = accounts[current].0.clone();
+                if result.is_ok() {
+                    let mut dial =
+                        Dial::new(accounts, current, &failed_over, config.rotation.as_ref());
+                    dial.lease = pool.cloned().zip(leased);
+                    alive.push(dial);
                 }
+                let mut outcome = Outcome::new(username, addr, result.map(Some));
+                outcome.failed_over = failed_over;
+                outcomes.push(outcome);
+            }
+            print_summary(
+                &outcomes,
+                parse_opt(&matches, "summary").unwrap_or_default(),
+            );
+            let code = exit_code(&outcomes);
+            if alive.is_empty() && code != 0 {
+                process::exit(code);
+            }
+            let interval = matches.opt_present("continue").then(|| {
+                parse_opt(&matches, "interval")
+                    .or(config.check_interval)
+                    .unwrap_or(KEEP_ALIVE_INTERVAL)
+            });
+            keep_alive(&mut alive, interval, config.rotation.as_ref());
+            if code != 0 {
+                process::exit(code);
             }
         }
         Err(e) => {
@@ -165,7 +1350,7 @@ fn config_login(matches: Matches) {
 fn single_login(matches: Matches) {
     let auth_server = match matches.opt_str("s") {
         Some(u) => u,
-        None => format!("http://{}", env!("AUTH_SERVER_IP")),
+        None => default_auth_server(),
     };
     let username = match matches.opt_str("u") {
         Some(u) => u,
@@ -182,37 +1367,68 @@ fn single_login(matches: Matches) {
         }
     };
     let detect_ip = matches.opt_present("d");
+    let protocol = parse_opt::<Protocol>(&matches, "protocol").unwrap_or_default();
     let ip = match matches.opt_str("i") {
         Some(u) => u,
         None => {
-            if matches.opt_present("select-ip") {
-                select_ip().unwrap_or_default()
-            } else if detect_ip {
+            let selector = ip_selector(&matches, &IpSelector::default(), &auth_server);
+            if !selector.is_empty() {
+                selected_ip(&selector, matches.opt_str("netns").as_deref())
+            } else if matches.opt_present("select-ip") {
+                select_ip_in(matches.opt_str("netns").as_deref())
+            } else if detect_ip || protocol == Protocol::Srun3k {
                 String::new()
             } else {
                 println!("need ip");
                 println!("  1. use '-i IP' to specify ip");
                 println!("  2. use '-d' to auto detect ip");
                 println!("  3. use '--select-ip' to select ip");
+                println!("  4. use '--ip-in', '--ip-route-to' or '--if-match' to pick ip");
                 return;
             }
         }
     };
     let test = matches.opt_present("test");
     let strict_bind = matches.opt_present("strict-bind");
+    let kick = matches
+        .opt_present("kick")
+        .then(|| parse_opt::<KickPolicy>(&matches, "kick-policy").unwrap_or_default());
 
     let user = User {
-        username,
+        username: username.clone(),
         password,
         ip: Some(ip),
-        if_name: None,
+        ipv6: matches.opt_str("ipv6"),
+        operator: parse_opt(&matches, "operator"),
+        fwmark: parse_opt(&matches, "fwmark"),
+        netns: matches.opt_str("netns"),
+        ..Default::default()
     };
     println!("login user: {:#?}", user);
-    let mut client = SrunClient::new_from_user(&auth_server, user)
+    let template = username_template(&matches, &None);
+    let state_key = state_key(&user);
+    let mut client = SrunClient::new_from_user_with_template(&auth_server, user, &template)
         .set_detect_ip(detect_ip)
         .set_test_before_login(test)
         .set_strict_bind(strict_bind)
-        .set_double_stack(matches.opt_present("double-stack"));
+        .set_bind_interface(matches.opt_str("bind-interface"))
+        .set_double_stack(matches.opt_present("double-stack"))
+        .set_auth_server6(matches.opt_str("server6"))
+        .set_state_dir(matches.opt_str("state-dir").map(PathBuf::from))
+        .set_state_key(state_key)
+        .set_kick(kick);
+    client.set_protocol(protocol);
+    let (checks, check_rule) = checks(&matches, &Config::default());
+    client.set_checks(checks, check_rule);
+    if matches.opt_present("detect-encoding")
+        && let Some(encoding) = detect_encoding(&auth_server)
+    {
+        client.set_encoding(encoding);
+    }
+
+    if let Some(device) = parse_opt::<DevicePreset>(&matches, "device") {
+        client.set_device(device);
+    }
 
     if let Some(n) = matches.opt_str("n") {
         client.set_n(n.parse().unwrap());
@@ -246,38 +1462,195 @@ fn single_login(matches: Matches) {
         eprintln!("login error: {}", e);
         process::exit(1);
     }
+    if matches.opt_present("continue") {
+        let interval = parse_opt(&matches, "interval").unwrap_or(KEEP_ALIVE_INTERVAL);
+        let mut dial = Dial::new(vec![(username, client, None)], 0, &[], None);
+        keep_alive(std::slice::from_mut(&mut dial), Some(interval), None);
+    }
 }
 
-fn config_logout(matches: Matches) {
+// keeps going after a failed user, returns the exit code
+fn config_logout(matches: Matches, if_names: &[String]) -> i32 {
     let config_path = matches.opt_str("c").unwrap();
     match read_config_from_file(config_path) {
-        Ok(config) => {
+        Ok(mut config) => {
+            config.assign_if_names(if_names);
             let config_i = config.clone();
             let auth_server = config
                 .server
                 .clone()
                 .unwrap_or_else(|| match matches.opt_str("s") {
                     Some(u) => u,
-                    None => format!("http://{}", env!("AUTH_SERVER_IP")),
-                });
-            for user in config_i {
-                println!("logout user: {:#?}", user);
-                let ip = user.ip.unwrap_or_else(|| {
-                    get_ip_by_if_name(&user.if_name.unwrap_or_default()).unwrap_or_default()
+                    None => default_auth_server(),
                 });
-                let mut client = SrunClient::new_for_logout(&auth_server, &user.username, &ip)
-                    .set_detect_ip(config.detect_ip)
-                    .set_strict_bind(config.strict_bind);
-
-                if let Some(acid) = config.acid {
-                    client.set_acid(acid);
+            let server6 = config
+                .server6
+                .clone()
+                .or_else(|| matches.opt_str("server6"));
+            let protocol = parse_opt(&matches, "protocol").or(config.protocol);
+            let template = username_template(&matches, &config.username_template);
+            let state_dir = state_dir(&matches, config.state_dir.as_ref());
+            let mut logouts = Vec::new();
+            let mut unselected = Vec::new();
+            let users = config_users(&matches, config_i);
+            let pool = config.pool.as_ref();
+            let (members, users) = match pool {
+                Some(pool) => pool.split(users),
+                None => (Vec::new(), users),
+            };
+            let mut dials: Vec<(Option<String>, Vec<User>)> = group_users(users, &config.failover)
+                .into_iter()
+                .map(|dial| (None, dial))
+                .collect();
+            // only the accounts leased to pool interfaces are online
+            if let Some(pool) = pool
+                && let Some(leased) = with_leases(pool, |leases| {
+                    pool.if_names
+                        .iter()
+                        .filter_map(|if_name| {
+                            Some((if_name.clone(), leases.get(if_name)?.to_owned()))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            {
+                for (if_name, account) in leased {
+                    if let Some(user) = members.iter().find(|user| user.label() == account) {
+                        let mut user = user.clone();
+                        user.if_name = Some(if_name.clone());
+                        user.ip = None;
+                        dials.push((Some(if_name), vec![user]));
+                    }
                 }
+            }
+            for (leased, dial) in dials {
+                let mut accounts = Vec::new();
+                for user in dial {
+                    println!("logout user: {:#?}", user);
+                    let username = user.full_username(&template);
+                    let netns = user.netns.clone();
+                    // the address that was logged in, the interface may have a new one by now
+                    let state_key = state_key(&user);
+                    let state = match UserState::load(&state_dir, &state_key) {
+                        Ok(state) => state,
+                        Err(e) => {
+                            eprintln!("state error: {}", e);
+                            UserState::default()
+                        }
+                    };
+                    let online = state.online_ip.is_some() || state.online_ipv6.is_some();
+                    let selector = user_selector(&matches, &user, &config.select, &auth_server);
+                    let ip = match state.online_ip {
+                        Some(ip) => Ok(ip),
+                        None => config_user_ip(&user, &selector),
+                    };
+                    let ip = match ip {
+                        Ok(ip) => ip,
+                        Err(e) => {
+                            eprintln!("{}: {}", user.label(), e);
+                            unselected.push(Outcome::new(user.label().to_owned(), None, Err(e)));
+                            continue;
+                        }
+                    };
+                    // strict bind needs an address the host still has
+                    let local_ip = if online && config.strict_bind {
+                        config_user_ip(&user, &selector).unwrap_or_default()
+                    } else {
+                        ip.clone()
+                    };
+                    // or at least its interface
+                    let bind_interface = bind_interface(
+                        user.if_name.as_deref().filter(|_| {
+                            config.bind_interface || (config.strict_bind && local_ip.is_empty())
+                        }),
+                        user.netns.as_deref(),
+                    )
+                    .or_else(|| matches.opt_str("bind-interface"));
+                    let ipv6 = state
+                        .online_ipv6
+                        .or_else(|| user.ipv6.clone())
+                        .unwrap_or_else(|| if_ipv6(user.if_name.clone(), user.netns.as_deref()));
+                    let mut client = SrunClient::new_for_logout(&auth_server, &username, &local_ip)
+                        .set_detect_ip(config.detect_ip)
+                        .set_strict_bind(config.strict_bind)
+                        .set_bind_interface(bind_interface)
+                        .set_fwmark(user.fwmark)
+                        .set_netns(netns)
+                        .set_double_stack(
+                            config.double_stack || matches.opt_present("double-stack"),
+                        )
+                        .set_auth_server6(server6.clone())
+                        .set_state_dir(Some(state_dir.clone()))
+                        .set_state_key(state_key);
+                    client.set_client_ip(&ip);
+                    client.set_ipv6(&ipv6);
 
-                if let Err(e) = client.logout() {
-                    eprintln!("logout error: {}", e);
-                    process::exit(1);
+                    if let Some(acid) = config.acid {
+                        client.set_acid(acid);
+                    }
+                    if let Some(logout_mode) = config.logout_mode {
+                        client.set_logout_mode(logout_mode);
+                    }
+                    if let Some(ref online_path) = config.online_path {
+                        client.set_online_path(online_path);
+                    }
+                    if let Some(protocol) = protocol {
+                        client.set_protocol(protocol);
+                        client.set_password(&user.password);
+                    }
+
+                    accounts.push((online, user.label().to_owned(), client));
+                }
+                // accounts known to be online go first
+                if accounts.is_empty() {
+                    continue;
                 }
+                accounts.sort_by_key(|(online, _, _)| !online);
+                let accounts: Vec<(String, SrunClient)> = accounts
+                    .into_iter()
+                    .map(|(_, label, client)| (label, client))
+                    .collect();
+                logouts.push((leased, accounts));
             }
+
+            let jobs = parse_opt(&matches, "jobs").or(config.jobs).unwrap_or(1);
+            let results = run_jobs(logouts, jobs, |(leased, accounts)| {
+                if jobs > 1 {
+                    output::set_prefix(&format!("[{}] ", accounts[0].0));
+                }
+                // which account of a failover group is online may be unknown,
+                // the first one the portal logs out wins
+                let mut outcome = None;
+                for (label, mut client) in accounts {
+                    let result = if matches.opt_present("all") {
+                        client.logout_all()
+                    } else {
+                        client.logout()
+                    };
+                    let ok = result.is_ok();
+                    outcome = Some(Outcome::new(
+                        label,
+                        None,
+                        result.map(|_| None).map_err(|e| e.to_string()),
+                    ));
+                    if ok {
+                        break;
+                    }
+                }
+                let outcome = outcome.expect("a dial has at least one account");
+                if let Some(ref e) = outcome.error {
+                    eoutln!("logout error: {}", e);
+                } else if let (Some(pool), Some(if_name)) = (pool, leased) {
+                    with_leases(pool, |leases| leases.release(&if_name));
+                }
+                outcome
+            });
+            let mut outcomes = unselected;
+            outcomes.extend(results);
+            print_summary(
+                &outcomes,
+                parse_opt(&matches, "summary").unwrap_or_default(),
+            );
+            exit_code(&outcomes)
         }
         Err(e) => {
             eprintln!("read config file error: {}", e);
@@ -289,7 +1662,7 @@ fn config_logout(matches: Matches) {
 fn logout(matches: Matches) {
     let auth_server = match matches.opt_str("s") {
         Some(u) => u,
-        None => format!("http://{}", env!("AUTH_SERVER_IP")),
+        None => default_auth_server(),
     };
     let username = match matches.opt_str("u") {
         Some(u) => u,
@@ -299,33 +1672,370 @@ fn logout(matches: Matches) {
         }
     };
     let detect_ip = matches.opt_present("d");
+    let logout_all = matches.opt_present("all");
+    let protocol = parse_opt::<Protocol>(&matches, "protocol").unwrap_or_default();
     let ip = match matches.opt_str("i") {
         Some(u) => u,
         None => {
-            if matches.opt_present("select-ip") {
-                select_ip().unwrap_or_default()
-            } else if detect_ip {
+            let selector = ip_selector(&matches, &IpSelector::default(), &auth_server);
+            if !selector.is_empty() {
+                selected_ip(&selector, matches.opt_str("netns").as_deref())
+            } else if matches.opt_present("select-ip") {
+                select_ip_in(matches.opt_str("netns").as_deref())
+            } else if detect_ip || logout_all || protocol == Protocol::Srun3k {
                 String::new()
             } else {
                 println!("need ip");
                 println!("  1. use '-i IP' to specify ip");
                 println!("  2. use '-d' to auto detect ip");
                 println!("  3. use '--select-ip' to select ip");
+                println!("  4. use '--ip-in', '--ip-route-to' or '--if-match' to pick ip");
+                println!("  5. use '--all' to logout all sessions");
                 return;
             }
         }
     };
     let strict_bind = matches.opt_present("strict-bind");
+    let state_key = UserState::key(&username, Some(&ip));
+    let username = User {
+        username,
+        operator: parse_opt(&matches, "operator"),
+        ..Default::default()
+    }
+    .full_username(&username_template(&matches, &None));
     let mut client = SrunClient::new_for_logout(&auth_server, &username, &ip)
         .set_detect_ip(detect_ip)
-        .set_strict_bind(strict_bind);
+        .set_strict_bind(strict_bind)
+        .set_bind_interface(matches.opt_str("bind-interface"))
+        .set_fwmark(parse_opt(&matches, "fwmark"))
+        .set_netns(matches.opt_str("netns"))
+        .set_double_stack(matches.opt_present("double-stack"))
+        .set_auth_server6(matches.opt_str("server6"))
+        .set_state_dir(matches.opt_str("state-dir").map(PathBuf::from))
+        .set_state_key(state_key);
+    if let Some(ref ipv6) = matches.opt_str("ipv6") {
+        client.set_ipv6(ipv6);
+    }
 
     if let Some(acid) = matches.opt_str("acid") {
         client.set_acid(acid.parse().unwrap());
     }
 
-    if let Err(e) = client.logout() {
+    if let Some(mode) = parse_opt::<LogoutMode>(&matches, "mode") {
+        client.set_logout_mode(mode);
+    }
+
+    client.set_protocol(protocol);
+    if let Some(ref password) = matches.opt_str("p") {
+        client.set_password(password);
+    }
+
+    let result = if logout_all {
+        client.logout_all()
+    } else {
+        client.logout()
+    };
+    if let Err(e) = result {
         eprintln!("logout error: {}", e);
         process::exit(1);
     }
 }
+
+fn status_match(args: &[String]) {
+    let options = {
+        let mut opts = Options::new();
+        opts.optflag("h", "help", "print help message");
+        opts.optopt("s", "server", "auth server", "");
+        opts.optopt("c", "config", "status of users in config file", "");
+        opts.optmulti(
+            "",
+            "user",
+            "only this user of the config, by name or username",
+            "",
+        );
+        opts.optmulti("", "tag", "only users of the config with this tag", "");
+        opts.optmulti(
+            "",
+            "exclude",
+            "leave out users of the config with this name or tag",
+            "",
+        );
+        opts.optopt(
+            "",
+            "username-template",
+            "how to append operator, default {username}@{operator}",
+            "",
+        );
+        opts.optopt("i", "ip", "ip", "");
+        opts.optflag("d", "detect", "detect client ip");
+        opts.optflag("", "select-ip", "select client ip");
+        opts.optopt(
+            "",
+            "ip-in",
+            "use the local ip inside this network, e.g. 10.0.0.0/8",
+            "",
+        );
+        opts.optflagopt(
+            "",
+            "ip-route-to",
+            "use the local ip routed to this host, default the auth server",
+            "",
+        );
+        opts.optopt(
+            "",
+            "if-match",
+            "use the ip of the interface matching this pattern",
+            "",
+        );
+        opts.optflag("", "strict-bind", "strict bind ip");
+        opts.optopt(
+            "",
+            "bind-interface",
+            "send requests out of this interface",
+            "",
+        );
+        opts.optopt(
+            "",
+            "fwmark",
+            "set SO_MARK on sockets, for policy routing",
+            "",
+        );
+        opts.optopt("", "netns", "run inside this named network namespace", "");
+        opts.optopt(
+            "",
+            "state-dir",
+            "keep the state of users in this directory",
+            "",
+        );
+        opts
+    };
+
+    let matches = match options.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("parse args error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(Some(&options));
+    } else if matches.opt_present("c") {
+        config_status(matches);
+    } else {
+        status(matches);
+    }
+}
+
+// false if the status could not be queried
+fn print_status(client: &mut SrunClient, operator: Option<Operator>) -> bool {
+    match client.status() {
+        Ok(info) => {
+            let operator = operator.or_else(|| Operator::from_username(&info.user_name));
+            match operator {
+                Some(operator) => println!("operator: {}", operator),
+                None => println!("operator: none"),
+            }
+            println!("{:#?}", info);
+            true
+        }
+        Err(e) => {
+            eprintln!("status error: {}", e);
+            false
+        }
+    }
+}
+
+fn config_status(matches: Matches) {
+    let config_path = matches.opt_str("c").unwrap();
+    match read_config_from_file(config_path) {
+        Ok(config) => {
+            let config_i = config.clone();
+            let auth_server = config
+                .server
+                .clone()
+                .unwrap_or_else(|| match matches.opt_str("s") {
+                    Some(u) => u,
+                    None => default_auth_server(),
+                });
+            let state_dir = state_dir(&matches, config.state_dir.as_ref());
+            let template = username_template(&matches, &config.username_template);
+            let (mut total, mut failed) = (0, 0);
+            for user in config_users(&matches, config_i) {
+                total += 1;
+                let username = user.full_username(&template);
+                let operator = user.operator.or_else(|| Operator::from_username(&username));
+                println!("status of user: {}", username);
+                let bind_interface = bind_interface(
+                    user.if_name.as_deref().filter(|_| config.bind_interface),
+                    user.netns.as_deref(),
+                )
+                .or_else(|| matches.opt_str("bind-interface"));
+                let netns = user.netns.clone();
+                let state_key = state_key(&user);
+                let selector = user_selector(&matches, &user, &config.select, &auth_server);
+                let ip = match config_user_ip(&user, &selector) {
+                    Ok(ip) => ip,
+                    Err(e) => {
+                        eprintln!("status error: {}", e);
+                        failed += 1;
+                        continue;
+                    }
+                };
+                let mut client = SrunClient::new_for_logout(&auth_server, &username, &ip)
+                    .set_detect_ip(config.detect_ip)
+                    .set_strict_bind(config.strict_bind)
+                    .set_bind_interface(bind_interface)
+                    .set_fwmark(user.fwmark)
+                    .set_netns(netns)
+                    .set_state_dir(Some(state_dir.clone()))
+                    .set_state_key(state_key);
+                if !print_status(&mut client, operator) {
+                    failed += 1;
+                }
+            }
+            let code = failure_code(failed, total);
+            if code != 0 {
+                process::exit(code);
+            }
+        }
+        Err(e) => {
+            eprintln!("read config file error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn status(matches: Matches) {
+    let auth_server = match matches.opt_str("s") {
+        Some(u) => u,
+        None => default_auth_server(),
+    };
+    let detect_ip = matches.opt_present("d");
+    let ip = match matches.opt_str("i") {
+        Some(u) => u,
+        None => {
+            let selector = ip_selector(&matches, &IpSelector::default(), &auth_server);
+            if !selector.is_empty() {
+                selected_ip(&selector, matches.opt_str("netns").as_deref())
+            } else if matches.opt_present("select-ip") {
+                select_ip_in(matches.opt_str("netns").as_deref())
+            } else {
+                String::new()
+            }
+        }
+    };
+    let mut client = SrunClient::new_for_logout(&auth_server, "", &ip)
+        .set_detect_ip(detect_ip)
+        .set_strict_bind(matches.opt_present("strict-bind"))
+        .set_bind_interface(matches.opt_str("bind-interface"))
+        .set_fwmark(parse_opt(&matches, "fwmark"))
+        .set_netns(matches.opt_str("netns"))
+        .set_state_dir(matches.opt_str("state-dir").map(PathBuf::from));
+    if !print_status(&mut client, None) {
+        process::exit(1);
+    }
+}
+
+fn probe_match(args: &[String]) {
+    let options = {
+        let mut opts = Options::new();
+        opts.optflag("h", "help", "print help message");
+        opts.optopt("s", "server", "auth server", "");
+        opts
+    };
+
+    let matches = match options.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("parse args error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(Some(&options));
+        return;
+    }
+
+    let auth_server = match matches.opt_str("s") {
+        Some(u) => u,
+        None => default_auth_server(),
+    };
+    match SrunClient::new_for_probe(&auth_server).probe() {
+        Ok(report) => println!("{:#?}", report),
+        Err(e) => {
+            eprintln!("probe error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// srun multidial up|down -c CONFIG, takes the login or logout options
+fn multidial_match(args: &[String]) {
+    let up = match args.get(2).map(String::as_str) {
+        Some("up") => true,
+        Some("down") => false,
+        _ => {
+            println!("Usage: srun multidial up|down -c CONFIG [options]");
+            return;
+        }
+    };
+    let options = if up {
+        login_options()
+    } else {
+        logout_options()
+    };
+    let matches = match options.parse(&args[2..]) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("parse args error: {}", e);
+            process::exit(1);
+        }
+    };
+    if matches.opt_present("h") {
+        print_usage(Some(&options));
+        return;
+    }
+    let Some(config_path) = matches.opt_str("c") else {
+        println!("need config, use '-c CONFIG'");
+        return;
+    };
+    let config = match read_config_from_file(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("read config file error: {}", e);
+            process::exit(1);
+        }
+    };
+    let Some(multidial) = config.multidial.clone() else {
+        eprintln!("no multidial section in config");
+        process::exit(1);
+    };
+    let users = config.user_count();
+    let if_names = multidial.if_names(users);
+
+    if up {
+        match multidial.up(users) {
+            Ok(ips) => {
+                for (if_name, ip) in ips {
+                    println!("{}: {}", if_name, ip);
+                }
+            }
+            Err(e) => {
+                eprintln!("multidial error: {}", e);
+                process::exit(1);
+            }
+        }
+        config_login(matches, &if_names);
+    } else {
+        let code = config_logout(matches, &if_names);
+        if let Err(e) = multidial.down(users) {
+            eprintln!("multidial error: {}", e);
+            process::exit(1);
+        }
+        if code != 0 {
+            process::exit(code);
+        }
+    }
+}
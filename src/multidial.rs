@@ -0,0 +1,255 @@
+use crate::{Result, srun::SrunError, utils::get_ip_by_if_name};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use std::{
+    fmt,
+    process::Command,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Macvlan interfaces created by `srun multidial up`, one per user.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MultiDial {
+    /// Physical interface the macvlans are created on.
+    pub parent: String,
+    /// Number of interfaces, defaults to the number of users.
+    pub count: Option<usize>,
+    /// Interfaces are named `{prefix}{i}`, counting from 0.
+    pub prefix: String,
+    pub mode: MacvlanMode,
+    /// Run for every interface to get an address, e.g. `udhcpc -b -i {if_name}`.
+    pub dhcp_command: Option<String>,
+    /// Seconds to wait for the addresses.
+    pub timeout: u64,
+}
+
+impl Default for MultiDial {
+    fn default() -> Self {
+        Self {
+            parent: String::new(),
+            count: None,
+            prefix: "macvlan".to_string(),
+            mode: MacvlanMode::default(),
+            dhcp_command: None,
+            timeout: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MacvlanMode {
+    Private,
+    Vepa,
+    #[default]
+    Bridge,
+    Passthru,
+}
+
+impl MacvlanMode {
+    // MACVLAN_MODE_* in linux/if_link.h
+    fn value(&self) -> u32 {
+        match self {
+            Self::Private => 1,
+            Self::Vepa => 2,
+            Self::Bridge => 4,
+            Self::Passthru => 8,
+        }
+    }
+}
+
+impl FromStr for MacvlanMode {
+    type Err = SrunError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "private" => Ok(Self::Private),
+            "vepa" => Ok(Self::Vepa),
+            "bridge" => Ok(Self::Bridge),
+            "passthru" => Ok(Self::Passthru),
+            _ => Err(SrunError::InvalidValue("macvlan mode", s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for MacvlanMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Private => "private",
+            Self::Vepa => "vepa",
+            Self::Bridge => "bridge",
+            Self::Passthru => "passthru",
+        })
+    }
+}
+
+impl MultiDial {
+    pub fn if_names(&self, users: usize) -> Vec<String> {
+        (0..self.count.unwrap_or(users))
+            .map(|i| format!("{}{}", self.prefix, i))
+            .collect()
+    }
+
+    /// Locally administered MAC derived from the names, so the DHCP server
+    /// hands out the same leases after every `up`.
+    pub fn mac(&self, if_name: &str) -> [u8; 6] {
+        let hash = Md5::digest(format!("{}/{}", self.parent, if_name));
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&hash[..6]);
+        mac[0] = (mac[0] & 0xfe) | 0x02;
+        mac
+    }
+
+    /// Create the interfaces and wait until each of them has an IPv4 address.
+    pub fn up(&self, users: usize) -> Result<Vec<(String, String)>> {
+        if self.parent.is_empty() {
+            return Err(Box::new(SrunError::InvalidValue(
+                "multidial parent",
+                self.parent.clone(),
+            )));
+        }
+        let if_names = self.if_names(users);
+        for if_name in &if_names {
+            if link::index(if_name).is_some() {
+                println!("{} exists", if_name);
+                continue;
+            }
+            link::add_macvlan(if_name, &self.parent, self.mac(if_name), self.mode)?;
+            println!("{} created on {}", if_name, self.parent);
+        }
+        if let Some(ref command) = self.dhcp_command {
+            for if_name in &if_names {
+                let command = command.replace("{if_name}", if_name);
+                Command::new("sh").arg("-c").arg(&command).spawn()?;
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(self.timeout);
+        loop {
+            let ips: Vec<Option<String>> = if_names
+                .iter()
+                .map(|if_name| get_ip_by_if_name(if_name))
+                .collect();
+            if ips.iter().all(Option::is_some) {
+                return Ok(if_names
+                    .into_iter()
+                    .zip(ips.into_iter().flatten())
+                    .collect());
+            }
+            if Instant::now() > deadline {
+                let missing: Vec<&str> = if_names
+                    .iter()
+                    .zip(ips)
+                    .filter(|(_, ip)| ip.is_none())
+                    .map(|(if_name, _)| if_name.as_str())
+                    .collect();
+                return Err(Box::new(SrunError::Timeout(format!(
+                    "no address on {}",
+                    missing.join(", ")
+                ))));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Delete the interfaces, missing ones are skipped.
+    pub fn down(&self, users: usize) -> Result<()> {
+        for if_name in self.if_names(users) {
+            if let Some(index) = link::index(&if_name) {
+                link::delete(index)?;
+                println!("{} deleted", if_name);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod link {
+    use super::MacvlanMode;
+    use crate::netlink::{MessageBuilder, Netlink};
+    use std::{ffi::CString, io};
+
+    // IFLA_MACVLAN_MODE in linux/if_link.h
+    const IFLA_MACVLAN_MODE: u16 = 1;
+
+    pub fn index(if_name: &str) -> Option<u32> {
+        let name = CString::new(if_name).ok()?;
+        match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+            0 => None,
+            index => Some(index),
+        }
+    }
+
+    pub fn add_macvlan(
+        if_name: &str,
+        parent: &str,
+        mac: [u8; 6],
+        mode: MacvlanMode,
+    ) -> io::Result<()> {
+        let parent = index(parent).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no interface {}", parent))
+        })?;
+        let name = CString::new(if_name)?;
+        let msg = MessageBuilder::new(libc::RTM_NEWLINK, libc::NLM_F_CREATE | libc::NLM_F_EXCL)
+            .ifinfo(0, libc::IFF_UP as u32, libc::IFF_UP as u32)
+            .attr(libc::IFLA_IFNAME, name.as_bytes_with_nul())
+            .attr(libc::IFLA_LINK, &parent.to_ne_bytes())
+            .attr(libc::IFLA_ADDRESS, &mac)
+            .begin(libc::IFLA_LINKINFO)
+            .attr(libc::IFLA_INFO_KIND, b"macvlan")
+            .begin(libc::IFLA_INFO_DATA)
+            .attr(IFLA_MACVLAN_MODE, &mode.value().to_ne_bytes())
+            .end()
+            .end();
+        Netlink::open(0)?.request(msg)
+    }
+
+    pub fn delete(index: u32) -> io::Result<()> {
+        let msg = MessageBuilder::new(libc::RTM_DELLINK, 0).ifinfo(index as i32, 0, 0);
+        Netlink::open(0)?.request(msg)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod link {
+    use super::MacvlanMode;
+    use std::io;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "multidial is only supported on linux",
+        )
+    }
+
+    pub fn index(_if_name: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn add_macvlan(_: &str, _: &str, _: [u8; 6], _: MacvlanMode) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn delete(_index: u32) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[test]
+fn test_mac() {
+    let multidial = MultiDial {
+        parent: "eth0".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(multidial.if_names(2), vec!["macvlan0", "macvlan1"]);
+    let mac = multidial.mac("macvlan0");
+    assert_eq!(mac, multidial.mac("macvlan0"));
+    assert_ne!(mac, multidial.mac("macvlan1"));
+    assert_eq!(mac[0] & 0x03, 0x02);
+}
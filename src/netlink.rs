@@ -0,0 +1,223 @@
+use std::{
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    time::Duration,
+};
+
+const NLMSG_HDRLEN: usize = 16;
+const RECV_BUF_SIZE: usize = 16 * 1024;
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// A rtnetlink socket, enough to manage links and watch addresses.
+pub(crate) struct Netlink {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+pub(crate) struct Message {
+    pub ty: u16,
+    pub seq: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Netlink {
+    /// Open a socket, subscribed to the `RTMGRP_*` multicast `groups`.
+    pub fn open(groups: u32) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = groups;
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd, seq: 0 })
+    }
+
+    /// Make `recv` give up with `WouldBlock` after `timeout`.
+    pub fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &tv as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Send a request and wait for the kernel to acknowledge it.
+    pub fn request(&mut self, msg: MessageBuilder) -> io::Result<()> {
+        self.seq += 1;
+        let buf = msg.finish(self.seq);
+        let ret = unsafe { libc::send(self.fd.as_raw_fd(), buf.as_ptr().cast(), buf.len(), 0) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        loop {
+            for msg in self.recv()? {
+                if msg.seq != self.seq || msg.ty != libc::NLMSG_ERROR as u16 {
+                    continue;
+                }
+                let code = msg
+                    .payload
+                    .get(..4)
+                    .map(|b| i32::from_ne_bytes(b.try_into().unwrap()))
+                    .unwrap_or(-libc::EIO);
+                return match code {
+                    0 => Ok(()),
+                    code => Err(io::Error::from_raw_os_error(-code)),
+                };
+            }
+        }
+    }
+
+    /// Block until the next batch of messages arrives.
+    pub fn recv(&self) -> io::Result<Vec<Message>> {
+        let mut buf = vec![0u8; RECV_BUF_SIZE];
+        let n = unsafe { libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let buf = &buf[..n as usize];
+        let mut msgs = Vec::new();
+        let mut off = 0;
+        while off + NLMSG_HDRLEN <= buf.len() {
+            let len = u32::from_ne_bytes(buf[off..off + 4].try_into().unwrap()) as usize;
+            if len < NLMSG_HDRLEN || off + len > buf.len() {
+                break;
+            }
+            msgs.push(Message {
+                ty: u16::from_ne_bytes(buf[off + 4..off + 6].try_into().unwrap()),
+                seq: u32::from_ne_bytes(buf[off + 8..off + 12].try_into().unwrap()),
+                payload: buf[off + NLMSG_HDRLEN..off + len].to_vec(),
+            });
+            off += align(len);
+        }
+        Ok(msgs)
+    }
+}
+
+/// Builds a request: header, a fixed size family header, then attributes.
+pub(crate) struct MessageBuilder {
+    buf: Vec<u8>,
+    nested: Vec<usize>,
+}
+
+impl MessageBuilder {
+    pub fn new(ty: u16, flags: i32) -> Self {
+        let mut buf = vec![0u8; NLMSG_HDRLEN];
+        buf[4..6].copy_from_slice(&ty.to_ne_bytes());
+        let flags = (flags | libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16;
+        buf[6..8].copy_from_slice(&flags.to_ne_bytes());
+        Self {
+            buf,
+            nested: Vec::new(),
+        }
+    }
+
+    /// `struct ifinfomsg`
+    pub fn ifinfo(mut self, index: i32, flags: u32, change: u32) -> Self {
+        self.buf
+            .extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+        self.buf.extend_from_slice(&index.to_ne_bytes());
+        self.buf.extend_from_slice(&flags.to_ne_bytes());
+        self.buf.extend_from_slice(&change.to_ne_bytes());
+        self
+    }
+
+    pub fn attr(mut self, ty: u16, data: &[u8]) -> Self {
+        self.buf
+            .extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+        self.buf.extend_from_slice(&ty.to_ne_bytes());
+        self.buf.extend_from_slice(data);
+        self.buf.resize(align(self.buf.len()), 0);
+        self
+    }
+
+    /// Start a nested attribute, closed by `end`.
+    pub fn begin(mut self, ty: u16) -> Self {
+        self.nested.push(self.buf.len());
+        self.attr(ty, &[])
+    }
+
+    pub fn end(mut self) -> Self {
+        let start = self.nested.pop().expect("unbalanced nested attribute");
+        let len = (self.buf.len() - start) as u16;
+        self.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        self
+    }
+
+    fn finish(mut self, seq: u32) -> Vec<u8> {
+        let len = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&len.to_ne_bytes());
+        self.buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+        self.buf
+    }
+}
+
+/// Split `data` into `(type, payload)` attributes.
+pub(crate) fn attrs(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = Vec::new();
+    while data.len() >= 4 {
+        let len = u16::from_ne_bytes([data[0], data[1]]) as usize;
+        let ty = u16::from_ne_bytes([data[2], data[3]]);
+        if len < 4 || len > data.len() {
+            break;
+        }
+        attrs.push((ty, &data[4..len]));
+        data = &data[align(len).min(data.len())..];
+    }
+    attrs
+}
+
+#[test]
+fn test_message_builder() {
+    let buf = MessageBuilder::new(libc::RTM_NEWLINK, 0)
+        .ifinfo(0, 0, 0)
+        .attr(libc::IFLA_IFNAME, b"mv0\0")
+        .begin(libc::IFLA_LINKINFO)
+        .attr(libc::IFLA_INFO_KIND, b"macvlan")
+        .end()
+        .finish(1);
+    assert_eq!(buf.len() % 4, 0);
+    assert_eq!(
+        u32::from_ne_bytes(buf[..4].try_into().unwrap()) as usize,
+        buf.len()
+    );
+    let parsed = attrs(&buf[NLMSG_HDRLEN + 16..]);
+    assert_eq!(parsed[0], (libc::IFLA_IFNAME, &b"mv0\0"[..]));
+    assert_eq!(parsed[1].0, libc::IFLA_LINKINFO);
+    assert_eq!(
+        attrs(parsed[1].1),
+        vec![(libc::IFLA_INFO_KIND, &b"macvlan"[..])]
+    );
+}
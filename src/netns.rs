@@ -0,0 +1,56 @@
+use crate::{Result, output, srun::SrunError};
+use std::{fs::File, io, panic, path::Path, thread};
+
+// where `ip netns add` mounts the named namespaces
+const NETNS_RUN_DIR: &str = "/var/run/netns";
+
+/// Run `f` on a thread that joined the named network namespace, or on the
+/// current thread if `netns` is `None`.
+///
+/// Sockets stay in the namespace they were created in, so they can still be
+/// used after `f` returns.
+pub fn in_netns<T: Send>(netns: Option<&str>, f: impl FnOnce() -> T + Send) -> Result<T> {
+    let Some(name) = netns else {
+        return Ok(f());
+    };
+    if name.is_empty() || name.contains('/') {
+        return Err(Box::new(SrunError::InvalidValue("netns", name.to_owned())));
+    }
+    let file = File::open(Path::new(NETNS_RUN_DIR).join(name))?;
+    let prefix = output::prefix();
+    thread::scope(|s| {
+        s.spawn(|| -> io::Result<T> {
+            output::set_prefix(&prefix);
+            setns(&file)?;
+            Ok(f())
+        })
+        .join()
+        .unwrap_or_else(|e| panic::resume_unwind(e))
+    })
+    .map_err(|e| e.into())
+}
+
+#[cfg(target_os = "linux")]
+fn setns(file: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // only the calling thread is moved, the rest of the process stays put
+    if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn setns(_file: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "network namespaces are only supported on linux",
+    ))
+}
+
+#[test]
+fn test_in_netns() {
+    assert_eq!(in_netns(None, || 1).unwrap(), 1);
+    assert!(in_netns(Some("../init"), || 1).is_err());
+}
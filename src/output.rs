@@ -0,0 +1,53 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    io::{self, Write},
+};
+
+thread_local! {
+    static PREFIX: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Start every line this thread prints with `prefix`, so the output of users
+/// logging in at the same time can be told apart.
+pub fn set_prefix(prefix: &str) {
+    PREFIX.with(|p| *p.borrow_mut() = prefix.to_owned());
+}
+
+pub fn prefix() -> String {
+    PREFIX.with(|p| p.borrow().clone())
+}
+
+// one write per message, lines of other threads do not end up in between
+#[doc(hidden)]
+pub fn print(args: fmt::Arguments, stderr: bool) {
+    let msg = args.to_string();
+    let prefix = prefix();
+    let mut buf = String::new();
+    for line in msg.split('\n') {
+        buf.push_str(&prefix);
+        buf.push_str(line);
+        buf.push('\n');
+    }
+    let _ = if stderr {
+        io::stderr().lock().write_all(buf.as_bytes())
+    } else {
+        io::stdout().lock().write_all(buf.as_bytes())
+    };
+}
+
+/// `println!` with the prefix of the current thread.
+#[macro_export]
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        $crate::output::print(format_args!($($arg)*), false)
+    };
+}
+
+/// `eprintln!` with the prefix of the current thread.
+#[macro_export]
+macro_rules! eoutln {
+    ($($arg:tt)*) => {
+        $crate::output::print(format_args!($($arg)*), true)
+    };
+}
@@ -0,0 +1,191 @@
+use crate::{Result, User, create_private_dir, lock_private_file, state_home};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek, Write},
+    path::PathBuf,
+};
+
+/// Accounts handed out to interfaces on the fly, when there are more
+/// accounts than interfaces. Every interface leases one free account.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AccountPool {
+    /// Interfaces that take an account from the pool each.
+    pub if_names: Vec<String>,
+    /// Names or usernames of config users in the pool, leased in this order.
+    pub users: Vec<String>,
+    /// Where the leases are kept, shared by every srun using the pool.
+    pub lease_file: PathBuf,
+    #[serde(skip)]
+    pid: Option<u32>,
+}
+
+impl Default for AccountPool {
+    fn default() -> Self {
+        Self {
+            if_names: Vec::new(),
+            users: Vec::new(),
+            lease_file: state_home().join("leases.json"),
+            pid: None,
+        }
+    }
+}
+
+impl AccountPool {
+    /// Mark the leases taken from now on with `pid`, for an srun that keeps
+    /// running. They can be taken over once the process is gone.
+    pub fn set_pid(mut self, pid: Option<u32>) -> Self {
+        self.pid = pid;
+        self
+    }
+
+    fn position(&self, user: &User) -> Option<usize> {
+        self.users
+            .iter()
+            .position(|name| user.label() == name || user.username == *name)
+    }
+
+    /// Split users into the pool members, in pool order, and the rest.
+    pub fn split(&self, users: Vec<User>) -> (Vec<User>, Vec<User>) {
+        let (mut members, rest): (Vec<User>, Vec<User>) = users
+            .into_iter()
+            .partition(|user| self.position(user).is_some());
+        members.sort_by_key(|user| self.position(user));
+        (members, rest)
+    }
+
+    /// Read the leases, the file stays locked until they are dropped.
+    pub fn lock(&self) -> Result<Leases> {
+        if let Some(dir) = self.lease_file.parent() {
+            create_private_dir(dir)?;
+        }
+        let mut file = lock_private_file(&self.lease_file)?;
+        let mut buf = String::new();
+        file.read_to_string(&mut buf)?;
+        let leases = if buf.trim().is_empty() {
+            BTreeMap::new()
+        } else {
+            serde_json::from_str(&buf)?
+        };
+        Ok(Leases {
+            file,
+            leases,
+            pid: self.pid,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Lease {
+    account: String,
+    /// The srun keeping the account online, none if it logged in and exited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+}
+
+impl Lease {
+    // the srun holding it crashed or was killed
+    fn is_stale(&self) -> bool {
+        self.pid.is_some_and(|pid| !is_running(pid))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    std::path::Path::new("/proc").join(pid.to_string()).exists()
+}
+
+// without a way to tell, a lease is never stale
+#[cfg(not(target_os = "linux"))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+/// The account each interface holds, by label.
+pub struct Leases {
+    file: File,
+    leases: BTreeMap<String, Lease>,
+    pid: Option<u32>,
+}
+
+impl Leases {
+    pub fn get(&self, if_name: &str) -> Option<&str> {
+        self.leases.get(if_name).map(|lease| lease.account.as_str())
+    }
+
+    /// The interface holding `account`, stale leases hold nothing.
+    pub fn holder(&self, account: &str) -> Option<&str> {
+        self.leases
+            .iter()
+            .find(|(_, lease)| lease.account == account && !lease.is_stale())
+            .map(|(if_name, _)| if_name.as_str())
+    }
+
+    /// Give `account` to `if_name` in place of its old lease, unless another
+    /// interface holds it. A stale lease of the account is taken over.
+    pub fn take(&mut self, if_name: &str, account: &str) -> bool {
+        if self.holder(account).is_some_and(|holder| holder != if_name) {
+            return false;
+        }
+        self.leases
+            .retain(|held_by, lease| held_by == if_name || lease.account != account);
+        self.leases.insert(
+            if_name.to_owned(),
+            Lease {
+                account: account.to_owned(),
+                pid: self.pid,
+            },
+        );
+        true
+    }
+
+    pub fn release(&mut self, if_name: &str) -> Option<String> {
+        self.leases.remove(if_name).map(|lease| lease.account)
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.rewind()?;
+        self.file
+            .write_all(serde_json::to_string_pretty(&self.leases)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_leases() {
+    let pool = AccountPool {
+        lease_file: std::env::temp_dir().join(format!("srun-leases-{}.json", std::process::id())),
+        ..Default::default()
+    };
+    let mut leases = pool.lock().unwrap();
+    assert!(leases.take("wan0", "a"));
+    assert!(!leases.take("wan1", "a"));
+    assert!(leases.take("wan1", "b"));
+    leases.save().unwrap();
+    drop(leases);
+
+    let mut leases = pool.lock().unwrap();
+    assert_eq!(leases.get("wan0"), Some("a"));
+    assert_eq!(leases.holder("b"), Some("wan1"));
+    assert!(leases.take("wan0", "c"));
+    assert_eq!(leases.holder("a"), None);
+    assert_eq!(leases.release("wan1").as_deref(), Some("b"));
+    leases.save().unwrap();
+    drop(leases);
+
+    // the lease of an srun that is gone is taken over
+    let crashed = pool.clone().set_pid(Some(u32::MAX));
+    let mut leases = crashed.lock().unwrap();
+    assert!(leases.take("wan2", "d"));
+    leases.save().unwrap();
+    drop(leases);
+    let mut leases = pool.lock().unwrap();
+    assert_eq!(leases.holder("d"), None);
+    assert!(leases.take("wan1", "d"));
+    assert_eq!(leases.get("wan2"), None);
+    drop(leases);
+    std::fs::remove_file(&pool.lease_file).unwrap();
+}
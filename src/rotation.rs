@@ -0,0 +1,88 @@
+use crate::UserInfo;
+use serde::{Deserialize, Serialize};
+
+/// What an account has left, as `rad_user_info` reports it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Quota {
+    pub remain_flux: f64,
+    pub wallet_balance: f64,
+}
+
+impl From<&UserInfo> for Quota {
+    fn from(info: &UserInfo) -> Self {
+        Self {
+            remain_flux: info.remain_bytes as f64,
+            wallet_balance: info.wallet_balance,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaKind {
+    /// Remaining traffic.
+    #[default]
+    Flux,
+    /// Wallet balance.
+    Balance,
+}
+
+impl QuotaKind {
+    pub fn of(&self, quota: &Quota) -> f64 {
+        match self {
+            Self::Flux => quota.remain_flux,
+            Self::Balance => quota.wallet_balance,
+        }
+    }
+}
+
+/// Move a dial to the account with the most quota left once the current one
+/// runs low, among the accounts of its failover group.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rotation {
+    #[serde(default)]
+    pub by: QuotaKind,
+    /// Rotate when the current account has less than this left, in bytes or
+    /// the currency of the balance. There is no default.
+    pub threshold: f64,
+    /// Seconds that have to pass between two rotations of a dial.
+    #[serde(default = "default_min_interval")]
+    pub min_interval: u64,
+}
+
+fn default_min_interval() -> u64 {
+    3600
+}
+
+impl Rotation {
+    /// The account to move to, given the last known quota of every account.
+    /// Accounts never seen are tried before the ones known to have less.
+    pub fn pick(&self, remain: &[Option<f64>], current: usize) -> Option<usize> {
+        let now = remain.get(current).copied().flatten()?;
+        if now >= self.threshold {
+            return None;
+        }
+        let known = |i: usize| remain[i].unwrap_or(f64::INFINITY);
+        (0..remain.len())
+            .filter(|&i| i != current)
+            .max_by(|&a, &b| known(a).total_cmp(&known(b)))
+            .filter(|&i| known(i) > now)
+    }
+}
+
+#[test]
+fn test_pick() {
+    let rotation = Rotation {
+        by: QuotaKind::Flux,
+        threshold: 100.0,
+        min_interval: 3600,
+    };
+    assert_eq!(rotation.pick(&[Some(500.0), Some(900.0)], 0), None);
+    assert_eq!(
+        rotation.pick(&[Some(50.0), Some(900.0), Some(300.0)], 0),
+        Some(1)
+    );
+    assert_eq!(rotation.pick(&[Some(50.0), Some(900.0), None], 0), Some(2));
+    assert_eq!(rotation.pick(&[Some(50.0), Some(10.0)], 0), None);
+    assert_eq!(rotation.pick(&[None, Some(900.0)], 0), None);
+}
@@ -1,14 +1,17 @@
 use crate::{
-    Result, User, param_i,
-    utils::{self, get_ip_by_if_name},
+    Check, CheckRule, DEFAULT_USERNAME_TEMPLATE, DevicePreset, EncodingProfile, PasswordMode,
+    Quota, Result, User, UserState, connectivity, eoutln, get_ipv6_by_if_name,
+    http_client::BindConnector, in_netns, outln, param_i_with, srun3k, utils::get_ip_by_if_name,
 };
 use hmac::{Hmac, Mac};
 use md5::Md5;
 use quick_error::quick_error;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use sha1::{Digest, Sha1};
 use std::{
-    net::IpAddr,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
     str::FromStr,
     thread,
     time::{Duration, SystemTime, UNIX_EPOCH},
@@ -16,21 +19,56 @@ use std::{
 
 const PATH_GET_CHALLENGE: &str = "/cgi-bin/get_challenge";
 const PATH_PORTAL: &str = "/cgi-bin/srun_portal";
+const PATH_DM: &str = "/cgi-bin/rad_user_dm";
+const PATH_ONLINE_DEVICES: &str = "/cgi-bin/rad_user_online";
+const PATH_USER_INFO: &str = "/cgi-bin/rad_user_info";
 
-#[derive(Default, Debug)]
+// srun reports "too many online devices" with this code
+const ECODE_DEVICE_LIMIT: &str = "E2620";
+// "Arrearage users."
+const ECODE_ARREARS: &str = "E2616";
+const ARREARS_WORDS: &[&str] = &["arrear", "欠费"];
+// used up traffic has no code of its own across srun versions, only these
+// messages, compared without the code in front
+const QUOTA_MESSAGES: &[&str] = &[
+    "flux limit exceeded",
+    "flux is used up",
+    "流量已用完",
+    "流量已用尽",
+];
+// first srun_ver that requires the signed rad_user_dm logout
+const SIGNED_LOGOUT_SINCE: (u32, u32) = (1, 18);
+// warn if the local clock is further off than this, in seconds
+const MAX_TIME_SKEW: i64 = 60;
+// portal pages pull in a lot of scripts, the encoder is in one of the first
+const MAX_SCANNED_SCRIPTS: usize = 8;
+
+#[derive(Default, Debug, Clone)]
 pub struct SrunClient {
     auth_server: String,
+    auth_server6: Option<String>,
 
     username: String,
     password: String,
     ip: String,
+    ipv6: String,
     client_ip: String,
     detect_ip: bool,
     strict_bind: bool,
+    bind_interface: Option<String>,
+    fwmark: Option<u32>,
+    netns: Option<String>,
 
     retry_delay: u32, // millis
     retry_times: u32,
     test_before_login: bool,
+    checks: Vec<Check>,
+    check_rule: CheckRule,
+    kick: Option<KickPolicy>,
+    online_path: String,
+    logout_mode: LogoutMode,
+    protocol: Protocol,
+    encoding: EncodingProfile,
 
     acid: i32,
     double_stack: i32,
@@ -41,6 +79,12 @@ pub struct SrunClient {
     n: i32,
     utype: i32,
     time: u64,
+    time_offset: i64, // server time minus local time
+    uid: String,      // srun3k session id
+    access_token: String,
+    server_version: String,
+    state_dir: Option<PathBuf>,
+    state_key: String,
 }
 
 quick_error! {
@@ -48,20 +92,151 @@ quick_error! {
     pub enum SrunError {
         GetChallengeFailed
         IpUndefinedError
+        DeviceLimit(msg: String) {
+            display("device limit reached: {}", msg)
+        }
+        Arrears(msg: String) {
+            display("account in arrears: {}", msg)
+        }
+        Quota(msg: String) {
+            display("quota used up: {}", msg)
+        }
+        Portal(msg: String) {
+            display("portal error: {}", msg)
+        }
+        InvalidValue(what: &'static str, value: String) {
+            display("invalid {}: {}", what, value)
+        }
+        Unsupported(what: &'static str) {
+            display("not supported: {}", what)
+        }
+        Timeout(msg: String) {
+            display("timeout: {}", msg)
+        }
+        SelectIp(msg: String) {
+            display("select ip: {}", msg)
+        }
+    }
+}
+
+/// Which online sessions to log out when the device limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KickPolicy {
+    /// Kick the session that has been online the longest.
+    #[default]
+    Oldest,
+    /// Kick the most recently logged in session.
+    Newest,
+    /// Kick every other session of the account.
+    All,
+}
+
+impl FromStr for KickPolicy {
+    type Err = SrunError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "oldest" => Ok(Self::Oldest),
+            "newest" => Ok(Self::Newest),
+            "all" => Ok(Self::All),
+            _ => Err(SrunError::InvalidValue("kick policy", s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for KickPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Oldest => "oldest",
+            Self::Newest => "newest",
+            Self::All => "all",
+        })
+    }
+}
+
+/// Portal protocol spoken by the auth server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// `get_challenge` + `{SRBX1}` flow of srun4k.
+    #[default]
+    Srun,
+    /// Form encoded `do_login` flow of the older srun3000 portal.
+    Srun3k,
+}
+
+impl FromStr for Protocol {
+    type Err = SrunError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "srun" => Ok(Self::Srun),
+            "srun3k" => Ok(Self::Srun3k),
+            _ => Err(SrunError::InvalidValue("protocol", s.to_owned())),
+        }
+    }
+}
+
+/// How to end a session, newer srun builds reject the unsigned `srun_portal` logout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogoutMode {
+    /// Pick `Legacy` or `Signed` from the `srun_ver` reported by the server.
+    #[default]
+    Auto,
+    /// `srun_portal?action=logout` with only username, ip and ac_id.
+    Legacy,
+    /// `rad_user_dm` with a timestamped sign.
+    Signed,
+    /// Like `Signed`, but also unbinds the device from the account.
+    Unbind,
+}
+
+impl FromStr for LogoutMode {
+    type Err = SrunError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "legacy" => Ok(Self::Legacy),
+            "signed" => Ok(Self::Signed),
+            "unbind" => Ok(Self::Unbind),
+            _ => Err(SrunError::InvalidValue("logout mode", s.to_owned())),
+        }
     }
 }
 
 impl SrunClient {
     pub fn new_from_user(auth_server: &str, user: User) -> Self {
-        let ip = user
-            .ip
-            .unwrap_or_else(|| get_ip_by_if_name(&user.if_name.unwrap()).unwrap_or_default());
+        Self::new_from_user_with_template(auth_server, user, DEFAULT_USERNAME_TEMPLATE)
+    }
+
+    /// Like `new_from_user`, with a school specific way to append the operator.
+    pub fn new_from_user_with_template(auth_server: &str, user: User, template: &str) -> Self {
+        let username = user.full_username(template);
+        let if_name = user.if_name.unwrap_or_default();
+        let ip = user.ip.unwrap_or_else(|| {
+            in_netns(user.netns.as_deref(), || get_ip_by_if_name(&if_name))
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+        });
+        let ipv6 = user.ipv6.unwrap_or_else(|| {
+            in_netns(user.netns.as_deref(), || get_ipv6_by_if_name(&if_name))
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+        });
         Self {
             auth_server: auth_server.to_owned(),
-            username: user.username,
+            username,
             password: user.password,
             ip: ip.clone(),
+            ipv6,
             client_ip: ip,
+            fwmark: user.fwmark,
+            netns: user.netns,
             acid: 12,
             n: 200,
             utype: 1,
@@ -69,6 +244,15 @@ impl SrunClient {
             name: "Windows".to_string(),
             retry_delay: 1000,
             retry_times: 3,
+            online_path: PATH_ONLINE_DEVICES.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_for_probe(auth_server: &str) -> Self {
+        Self {
+            auth_server: auth_server.to_owned(),
+            online_path: PATH_ONLINE_DEVICES.to_string(),
             ..Default::default()
         }
     }
@@ -78,6 +262,8 @@ impl SrunClient {
             auth_server: auth_server.to_owned(),
             username: username.to_owned(),
             ip: ip.to_owned(),
+            client_ip: ip.to_owned(),
+            online_path: PATH_ONLINE_DEVICES.to_string(),
             ..Default::default()
         }
     }
@@ -92,11 +278,43 @@ impl SrunClient {
         self
     }
 
+    /// Send every request out of this interface, regardless of routing.
+    pub fn set_bind_interface(mut self, interface: Option<String>) -> Self {
+        self.bind_interface = interface;
+        self
+    }
+
+    pub fn bind_interface(&self) -> Option<&str> {
+        self.bind_interface.as_deref()
+    }
+
+    pub fn set_fwmark(mut self, mark: Option<u32>) -> Self {
+        self.fwmark = mark;
+        self
+    }
+
+    /// Open every socket inside this named network namespace.
+    pub fn set_netns(mut self, netns: Option<String>) -> Self {
+        self.netns = netns;
+        self
+    }
+
+    pub(crate) fn netns(&self) -> Option<&str> {
+        self.netns.as_deref()
+    }
+
+    /// Also log in the IPv6 address, set by `set_ipv6` or found on `if_name`.
     pub fn set_double_stack(mut self, b: bool) -> Self {
         self.double_stack = b as i32;
         self
     }
 
+    /// Auth server of the IPv6 session, if not the same as the IPv4 one.
+    pub fn set_auth_server6(mut self, server: Option<String>) -> Self {
+        self.auth_server6 = server;
+        self
+    }
+
     pub fn set_n(&mut self, n: i32) {
         self.n = n;
     }
@@ -117,6 +335,14 @@ impl SrunClient {
         self.name = name.to_string();
     }
 
+    /// Fill `os`, `name`, `n` and `type` from a preset.
+    pub fn set_device(&mut self, device: DevicePreset) {
+        self.os = device.os().to_string();
+        self.name = device.name().to_string();
+        self.n = device.n();
+        self.utype = device.utype();
+    }
+
     pub fn set_retry_delay(&mut self, d: u32) {
         self.retry_delay = d;
     }
@@ -130,83 +356,319 @@ impl SrunClient {
         self
     }
 
+    /// Probes that decide whether the network is already connected.
+    pub fn set_checks(&mut self, checks: Vec<Check>, rule: CheckRule) {
+        self.checks = checks;
+        self.check_rule = rule;
+    }
+
+    /// Run the connectivity checks, the default checks are used if none is set.
+    pub fn check_online(&self) -> bool {
+        if self.checks.is_empty() {
+            connectivity::run(self, &Check::defaults(), self.check_rule)
+        } else {
+            connectivity::run(self, &self.checks, self.check_rule)
+        }
+    }
+
+    /// Log out other sessions chosen by `policy` when login hits the device limit.
+    pub fn set_kick(mut self, policy: Option<KickPolicy>) -> Self {
+        self.kick = policy;
+        self
+    }
+
+    /// Keep what login, logout and status learn about the user in this directory.
+    pub fn set_state_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.state_dir = dir;
+        self
+    }
+
+    /// The state to keep, see [`UserState::key`], nothing is kept without one.
+    pub fn set_state_key(mut self, key: String) -> Self {
+        self.state_key = key;
+        self
+    }
+
+    pub fn set_online_path(&mut self, path: &str) {
+        self.online_path = path.to_string();
+    }
+
+    pub fn set_logout_mode(&mut self, mode: LogoutMode) {
+        self.logout_mode = mode;
+    }
+
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// srun3k logs out by username and password when the session uid is unknown.
+    pub fn set_password(&mut self, password: &str) {
+        self.password = password.to_string();
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn ip(&self) -> &str {
+        &self.ip
+    }
+
+    /// Switch to a new address, e.g. after the DHCP lease changed.
+    pub fn set_ip(&mut self, ip: &str) {
+        self.ip = ip.to_owned();
+        self.client_ip = ip.to_owned();
+    }
+
+    /// Log in or out `ip` while strict bind still binds to the own address,
+    /// e.g. for a session of an address the interface no longer has.
+    pub fn set_client_ip(&mut self, ip: &str) {
+        self.client_ip = ip.to_owned();
+    }
+
+    /// What the account online on the client ip has left, the traffic in
+    /// bytes as `rad_user_info` counts it.
+    pub fn quota(&self) -> Result<Quota> {
+        Ok(Quota::from(&self.user_info()?))
+    }
+
+    pub fn ipv6(&self) -> &str {
+        &self.ipv6
+    }
+
+    pub fn set_ipv6(&mut self, ip: &str) {
+        self.ipv6 = ip.to_owned();
+    }
+
+    // a second client for the IPv6 session, if double stack has one to log in
+    fn v6_client(&self) -> Option<Self> {
+        if self.double_stack == 0 || self.ipv6.is_empty() || self.ipv6 == self.client_ip {
+            return None;
+        }
+        let mut client = self.clone();
+        if let Some(ref server) = self.auth_server6 {
+            client.auth_server = server.clone();
+        }
+        // `ip` stays the IPv4 one, for strict bind and to spare it from kicking
+        client.client_ip = self.ipv6.clone();
+        client.detect_ip = false;
+        Some(client)
+    }
+
+    // strict bind uses the address of the auth server's family, and none if
+    // there is no address of that family
+    fn bind_ip(&self) -> Result<Option<IpAddr>> {
+        if !self.strict_bind {
+            return Ok(None);
+        }
+        let ip = if is_ipv6_url(&self.auth_server) && !self.ipv6.is_empty() {
+            &self.ipv6
+        } else {
+            &self.ip
+        };
+        if ip.is_empty() {
+            return Ok(None);
+        }
+        let ip = IpAddr::from_str(ip)?;
+        if url_ip(&self.auth_server).is_some_and(|server| server.is_ipv6() != ip.is_ipv6()) {
+            return Ok(None);
+        }
+        Ok(Some(ip))
+    }
+
+    pub fn set_encoding(&mut self, encoding: EncodingProfile) {
+        self.encoding = encoding;
+    }
+
+    /// Look for the encoding profile in the portal page and its scripts.
+    pub fn detect_encoding(&self) -> Result<Option<EncodingProfile>> {
+        let page = self.get_raw(&format!("{}/", self.auth_server), vec![])?;
+        let page = String::from_utf8_lossy(&page);
+        if let Some(profile) = EncodingProfile::scan(&page) {
+            return Ok(Some(profile));
+        }
+        let scripts = page
+            .split("src=")
+            .skip(1)
+            .filter_map(|s| s.split(['"', '\'']).nth(1))
+            .filter(|s| s.ends_with(".js"))
+            .take(MAX_SCANNED_SCRIPTS);
+        for src in scripts {
+            let url = if src.starts_with("http") {
+                src.to_string()
+            } else {
+                format!("{}/{}", self.auth_server, src.trim_start_matches('/'))
+            };
+            let Ok(script) = self.get_raw(&url, vec![]) else {
+                continue;
+            };
+            if let Some(profile) = EncodingProfile::scan(&String::from_utf8_lossy(&script)) {
+                return Ok(Some(profile));
+            }
+        }
+        Ok(None)
+    }
+
     #[cfg(feature = "reqwest")]
-    pub fn get_http_client(&self) -> Result<reqwest::blocking::Client> {
-        Ok(if self.strict_bind && !self.ip.is_empty() {
-            let local_addr = IpAddr::from_str(&self.ip)?;
-            reqwest::blocking::ClientBuilder::default()
+    fn http_client_builder(&self) -> Result<reqwest::blocking::ClientBuilder> {
+        if self.fwmark.is_some() {
+            return Err(Box::new(SrunError::Unsupported(
+                "fwmark with reqwest, use ureq",
+            )));
+        }
+        let mut builder = reqwest::blocking::ClientBuilder::default();
+        if let Some(local_addr) = self.bind_ip()? {
+            builder = builder
                 .local_address(local_addr)
-                .connect_timeout(Duration::from_secs(3))
-                .build()?
-        } else {
-            reqwest::blocking::Client::default()
-        })
+                .connect_timeout(Duration::from_secs(3));
+        }
+        if let Some(ref interface) = self.bind_interface {
+            #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+            {
+                builder = builder.interface(interface);
+            }
+            #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+            {
+                let _ = interface;
+                return Err(Box::new(SrunError::Unsupported(
+                    "bind_interface on this platform",
+                )));
+            }
+        }
+        Ok(builder)
+    }
+
+    // the client's connections are made on a runtime thread spawned by build,
+    // which inherits the namespace
+    #[cfg(feature = "reqwest")]
+    fn build_http_client(
+        &self,
+        builder: reqwest::blocking::ClientBuilder,
+    ) -> Result<reqwest::blocking::Client> {
+        Ok(in_netns(self.netns(), || builder.build())??)
+    }
+
+    #[cfg(feature = "reqwest")]
+    pub fn get_http_client(&self) -> Result<reqwest::blocking::Client> {
+        self.build_http_client(self.http_client_builder()?)
+    }
+
+    /// Sockets made the way the client's requests are, for connectivity checks.
+    pub(crate) fn connector(&self) -> Result<BindConnector> {
+        Ok(BindConnector::default()
+            .set_bind_addr(self.bind_ip()?.map(|ip| SocketAddr::new(ip, 0)))
+            .set_interface(self.bind_interface.clone())
+            .set_mark(self.fwmark)
+            .set_netns(self.netns.clone()))
+    }
+
+    #[cfg(feature = "ureq")]
+    fn http_client_builder(&self) -> Result<ureq::AgentBuilder> {
+        let mut builder = ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(5));
+        let connector = self.connector()?;
+        if connector.is_bound() || self.netns.is_some() {
+            builder = builder.connector(connector);
+        }
+        Ok(builder)
     }
 
     #[cfg(feature = "ureq")]
     pub fn get_http_client(&self) -> Result<ureq::Agent> {
-        use crate::http_client::BindConnector;
-        use std::net::SocketAddr;
-
-        Ok(if self.strict_bind && !self.ip.is_empty() {
-            let local_addr_ip = IpAddr::from_str(&self.ip)?;
-            ureq::AgentBuilder::new()
-                .connector(BindConnector::new_bind(SocketAddr::new(local_addr_ip, 0)))
-                .timeout_connect(Duration::from_secs(5))
-                .build()
-        } else {
-            ureq::AgentBuilder::new()
-                .timeout_connect(Duration::from_secs(5))
-                .build()
+        Ok(self.http_client_builder()?.build())
+    }
+
+    // status code of a GET without following redirects
+    pub(crate) fn http_status(&self, url: &str) -> Result<u16> {
+        #[cfg(feature = "reqwest")]
+        {
+            let client = self.build_http_client(
+                self.http_client_builder()?
+                    .redirect(reqwest::redirect::Policy::none())
+                    .timeout(Duration::from_secs(5)),
+            )?;
+            Ok(client.get(url).send()?.status().as_u16())
+        }
+        #[cfg(feature = "ureq")]
+        {
+            let agent = self
+                .http_client_builder()?
+                .redirects(0)
+                .timeout(Duration::from_secs(5))
+                .build();
+            match agent.get(url).call() {
+                Ok(resp) => Ok(resp.status()),
+                Err(ureq::Error::Status(code, _)) => Ok(code),
+                Err(e) => Err(Box::new(e)),
+            }
+        }
+    }
+
+    fn get_raw(&self, url: &str, query: Vec<(&str, &str)>) -> Result<Vec<u8>> {
+        let req = self.get_http_client()?.get(url);
+        Ok({
+            #[cfg(feature = "reqwest")]
+            {
+                req.query(&query).send()?.bytes()?.to_vec()
+            }
+            #[cfg(feature = "ureq")]
+            {
+                req.query_vec(query).call()?.into_string()?.into_bytes()
+            }
         })
     }
 
-    fn detect_ip(&mut self) -> Result<()> {
-        self.time = unix_second() - 2;
+    fn post_form(&self, path: &str, form: &[(&str, &str)]) -> Result<String> {
         let req = self
             .get_http_client()?
-            .get(format!("{}{}", self.auth_server, PATH_GET_CHALLENGE).as_str());
-
-        let time = self.time.to_string();
-
-        let query = vec![
-            ("callback", "sdu"),
-            ("username", &self.username),
-            ("ip", &self.client_ip),
-            ("_", &time),
-        ];
-
-        let challenge: ChallengeResponse = {
+            .post(format!("{}{}", self.auth_server, path).as_str());
+        Ok({
             #[cfg(feature = "reqwest")]
             {
-                let resp = req.query(&query).send()?.bytes()?;
-                serde_json::from_slice(&resp[4..resp.len() - 1])?
+                req.form(form).send()?.text()?
             }
             #[cfg(feature = "ureq")]
             {
-                let resp = req.query_vec(query).call()?.into_string()?;
-                let resp = resp.as_bytes();
-                serde_json::from_slice(&resp[4..resp.len() - 1])?
+                req.send_form(form)?.into_string()?
             }
-        };
-        if !challenge.online_ip.is_empty() {
-            self.client_ip = challenge.online_ip;
-        }
-        Ok(())
+        })
     }
 
-    fn get_token(&mut self) -> Result<String> {
-        if self.client_ip.is_empty() {
-            println!("need ip");
-            return Err(Box::new(SrunError::IpUndefinedError));
+    fn get_jsonp<T: DeserializeOwned>(&self, path: &str, query: Vec<(&str, &str)>) -> Result<T> {
+        let resp = self.get_raw(&format!("{}{}", self.auth_server, path), query)?;
+        Ok(serde_json::from_slice(strip_jsonp(&resp))?)
+    }
+
+    // anything but a 404 means the server knows the path
+    fn path_exists(&self, path: &str) -> bool {
+        let client = match self.get_http_client() {
+            Ok(client) => client,
+            Err(_) => return false,
+        };
+        let url = format!("{}{}", self.auth_server, path);
+        #[cfg(feature = "reqwest")]
+        {
+            client
+                .get(url)
+                .send()
+                .is_ok_and(|resp| resp.status() != reqwest::StatusCode::NOT_FOUND)
         }
+        #[cfg(feature = "ureq")]
+        {
+            match client.get(&url).call() {
+                Ok(_) => true,
+                Err(ureq::Error::Status(code, _)) => code != 404,
+                Err(_) => false,
+            }
+        }
+    }
 
-        self.time = unix_second() - 2;
-        let req = self
-            .get_http_client()?
-            .get(format!("{}{}", self.auth_server, PATH_GET_CHALLENGE).as_str());
+    // local clock adjusted by the offset learned from the last challenge
+    fn now(&self) -> u64 {
+        unix_second().saturating_add_signed(self.time_offset)
+    }
 
-        let time = self.time.to_string();
+    fn get_challenge(&mut self) -> Result<ChallengeResponse> {
+        let time = self.now().to_string();
 
         let query = vec![
             ("callback", "sdu"),
@@ -214,22 +676,37 @@ impl SrunClient {
             ("ip", &self.client_ip),
             ("_", &time),
         ];
-
-        let challenge: ChallengeResponse = {
-            #[cfg(feature = "reqwest")]
-            {
-                let resp = req.query(&query).send()?.bytes()?;
-                serde_json::from_slice(&resp[4..resp.len() - 1])?
-            }
-            #[cfg(feature = "ureq")]
-            {
-                let resp = req.query_vec(query).call()?.into_string()?;
-                let resp = resp.as_bytes();
-                serde_json::from_slice(&resp[4..resp.len() - 1])?
+        let challenge: ChallengeResponse = self.get_jsonp(PATH_GET_CHALLENGE, query)?;
+        if challenge.st != 0 {
+            let offset = challenge.st as i64 - unix_second() as i64;
+            if offset.abs() > MAX_TIME_SKEW && offset != self.time_offset {
+                outln!(
+                    "warning: local clock is {}s off from server time, using server time",
+                    offset
+                );
             }
-        };
+            self.time_offset = offset;
+        }
+        self.time = self.now();
+        Ok(challenge)
+    }
+
+    fn detect_ip(&mut self) -> Result<()> {
+        let challenge = self.get_challenge()?;
+        if !challenge.online_ip.is_empty() {
+            self.client_ip = challenge.online_ip;
+        }
+        Ok(())
+    }
 
-        println!("{:#?}", challenge);
+    fn get_token(&mut self) -> Result<String> {
+        if self.client_ip.is_empty() {
+            outln!("need ip");
+            return Err(Box::new(SrunError::IpUndefinedError));
+        }
+
+        let challenge = self.get_challenge()?;
+        outln!("{:#?}", challenge);
         match challenge.challenge.clone() {
             Some(token) => {
                 self.token = token;
@@ -241,21 +718,75 @@ impl SrunClient {
         Ok(self.token.clone())
     }
 
-    pub fn login(&mut self) -> Result<()> {
-        if self.test_before_login {
-            if let Ok(d) = utils::tcp_ping("baidu.com:80") {
-                println!(
-                    "Network already connected: tcping baidu.com:80, delay: {}ms",
-                    d
-                );
-                return Ok(());
+    /// Log in, returns the addresses that are online now.
+    pub fn login(&mut self) -> Result<Sessions> {
+        let result = self.start_sessions();
+        self.record(|state| match result {
+            // nothing new was learned
+            Ok(Sessions {
+                already_online: true,
+                ..
+            }) => {}
+            Ok(ref sessions) => {
+                state.login_time = Some(unix_second());
+                state.online_ip = sessions.ipv4.clone();
+                state.online_ipv6 = sessions.ipv6.clone();
+                state.access_token = Some(self.access_token.clone()).filter(|t| !t.is_empty());
+                if !self.server_version.is_empty() {
+                    state.server_version = Some(self.server_version.clone());
+                }
+                state.last_error = None;
             }
+            Err(ref e) => state.last_error = Some(e.to_string()),
+        });
+        result
+    }
+
+    fn start_sessions(&mut self) -> Result<Sessions> {
+        let mut sessions = Sessions::default();
+        if self.test_before_login && self.check_online() {
+            outln!("Network already connected");
+            sessions.already_online = true;
+            return Ok(sessions);
+        }
+
+        if self.protocol == Protocol::Srun3k {
+            self.login_3k()?;
+            sessions.push(&self.client_ip);
+            return Ok(sessions);
         }
 
         if self.detect_ip {
             self.detect_ip()?;
         }
 
+        self.try_login_kicking()?;
+        sessions.push(&self.client_ip);
+
+        if let Some(mut client) = self.v6_client() {
+            // the IPv4 session is up, a portal without IPv6 should not fail the login
+            match client.try_login_kicking() {
+                Ok(()) => sessions.push(&client.client_ip),
+                Err(e) => outln!("ipv6 login failed: {}", e),
+            }
+        }
+        outln!("online: {}", sessions);
+        Ok(sessions)
+    }
+
+    // make room and try again if the account is at its device limit
+    fn try_login_kicking(&mut self) -> Result<()> {
+        match self.try_login() {
+            Err(e) if self.kick.is_some() && is_device_limit(e.as_ref()) => {
+                outln!("{}, kicking other devices...", e);
+                self.kick()?;
+                self.try_login()
+            }
+            result => result,
+        }
+    }
+
+    fn try_login(&mut self) -> Result<()> {
         // this will detect ip from response if detect_ip
         self.get_token()?;
 
@@ -270,39 +801,38 @@ impl SrunClient {
             format!("{:x}", result.into_bytes())
         };
 
-        let param_i = param_i(
+        let param_i = param_i_with(
+            &self.encoding,
             &self.username,
             &self.password,
             &self.client_ip,
             self.acid,
             &self.token,
-        );
+        )?;
 
         let check_sum = {
-            let check_sum = [
-                "",
-                &self.username,
-                &hmd5,
-                &self.acid.to_string(),
-                &self.client_ip,
-                &self.n.to_string(),
-                &self.utype.to_string(),
-                &param_i,
-            ]
-            .join(&self.token);
+            let acid = self.acid.to_string();
+            let n = self.n.to_string();
+            let utype = self.utype.to_string();
+            let mut parts = vec!["", &self.username];
+            // a plain password has no hmac to sign
+            if self.encoding.password == PasswordMode::HmacMd5 {
+                parts.push(&hmd5);
+            }
+            parts.extend([acid.as_str(), &self.client_ip, &n, &utype, &param_i]);
+            let check_sum = parts.join(&self.token);
             let mut sha1_hasher = Sha1::new();
             sha1_hasher.update(check_sum);
             format!("{:x}", sha1_hasher.finalize())
         };
 
-        println!("will try at most {} times...", self.retry_times);
+        outln!("will try at most {} times...", self.retry_times);
         let mut result = PortalResponse::default();
         for ti in 1..=self.retry_times {
-            let req = self
-                .get_http_client()?
-                .get(format!("{}{}", self.auth_server, PATH_PORTAL).as_str());
-
-            let password = format!("{{MD5}}{}", hmd5);
+            let password = match self.encoding.password {
+                PasswordMode::HmacMd5 => format!("{{MD5}}{}", hmd5),
+                PasswordMode::Plain => self.password.clone(),
+            };
             let ac_id = self.acid.to_string();
             let n = self.n.to_string();
             let utype = self.utype.to_string();
@@ -325,72 +855,452 @@ impl SrunClient {
                 ("chksum", &check_sum),
                 ("_", &time),
             ];
-
-            result = {
-                #[cfg(feature = "reqwest")]
-                {
-                    let resp = req.query(&query).send()?.bytes()?;
-                    serde_json::from_slice(&resp[4..resp.len() - 1])?
-                }
-                #[cfg(feature = "ureq")]
-                {
-                    let resp = req.query_vec(query).call()?.into_string()?;
-                    let resp = resp.as_bytes();
-                    serde_json::from_slice(&resp[4..resp.len() - 1])?
-                }
-            };
+            result = self.get_jsonp(PATH_PORTAL, query)?;
 
             if !result.access_token.is_empty() {
-                println!("try {}/{}: success\n{:#?}", ti, self.retry_times, result);
+                self.access_token = result.access_token.clone();
+                self.server_version = if result.srun_ver.is_empty() {
+                    result.sysver.clone()
+                } else {
+                    result.srun_ver.clone()
+                };
+                outln!("try {}/{}: success\n{:#?}", ti, self.retry_times, result);
                 return Ok(());
             }
-            println!("try {}/{}: failed", ti, self.retry_times);
+            outln!("try {}/{}: failed", ti, self.retry_times);
+            if is_account_error(&result.to_error()) {
+                // retrying will not free a slot or top up the account
+                break;
+            }
             thread::sleep(Duration::from_millis(self.retry_delay as u64));
         }
-        println!("{:#?}", result);
-        Ok(())
+        outln!("{:#?}", result);
+        Err(Box::new(result.to_error()))
     }
 
-    pub fn logout(&mut self) -> Result<()> {
+    /// Online status of the client ip, detecting it first if enabled.
+    pub fn status(&mut self) -> Result<UserInfo> {
         if self.detect_ip {
             self.detect_ip()?;
         }
-        let req = self
-            .get_http_client()?
-            .get(format!("{}{}", self.auth_server, PATH_PORTAL).as_str());
+        let result = self.user_info();
+        match result {
+            // another account online on the ip is none of this user's business
+            Ok(ref info) if info.user_name == self.username && !self.username.is_empty() => self
+                .record(|state| {
+                    state.online_ip = Some(info.online_ip.clone()).filter(|ip| !ip.is_empty());
+                    if !info.sysver.is_empty() {
+                        state.server_version = Some(info.sysver.clone());
+                    }
+                    state.last_error = None;
+                }),
+            // the user is not online on the ip, which only ends the session
+            // kept for it, the user may be online on another one
+            Ok(_) => self.record(|state| {
+                if state.online_ip.as_deref() == Some(self.client_ip.as_str()) {
+                    state.online_ip = None;
+                    state.online_ipv6 = None;
+                    state.access_token = None;
+                }
+            }),
+            Err(ref e) => self.record(|state| {
+                state.last_error = Some(e.to_string());
+            }),
+        }
+        result
+    }
 
-        let ac_id = self.acid.to_string();
-        let time = unix_second().to_string();
+    // update the state kept for the user, if there is a state dir
+    fn record(&self, f: impl FnOnce(&mut UserState)) {
+        if let Some(ref dir) = self.state_dir
+            && !self.state_key.is_empty()
+            && let Err(e) = UserState::update(dir, &self.state_key, f)
+        {
+            eoutln!("state error: {}", e);
+        }
+    }
+
+    /// Query `rad_user_info` for the session of the current ip.
+    pub fn user_info(&self) -> Result<UserInfo> {
+        let time = self.now().to_string();
+        let query = vec![("callback", "sdu"), ("ip", &self.client_ip), ("_", &time)];
+        self.get_jsonp(PATH_USER_INFO, query)
+    }
+
+    /// Read what the server supports without logging in.
+    pub fn probe(&mut self) -> Result<ProbeReport> {
+        let time = unix_second();
+        let time_s = time.to_string();
         let query = vec![
             ("callback", "sdu"),
-            ("action", "logout"),
             ("username", &self.username),
             ("ip", &self.client_ip),
-            ("ac_id", &ac_id),
+            ("_", &time_s),
+        ];
+        let resp = self.get_raw(
+            &format!("{}{}", self.auth_server, PATH_GET_CHALLENGE),
+            query,
+        )?;
+        let body = strip_jsonp(&resp);
+        let challenge: ChallengeResponse = serde_json::from_slice(body)?;
+        let time_skew = challenge.st as i64 - time as i64;
+        if challenge.st != 0 {
+            self.time_offset = time_skew;
+        }
+
+        let tls = if self.auth_server.starts_with("https://") {
+            Some(true)
+        } else if cfg!(any(
+            feature = "ureq-rust-tls",
+            feature = "ureq-native-tls",
+            feature = "reqwest-rust-tls",
+            feature = "reqwest-native-tls"
+        )) {
+            let http_server = self.auth_server.clone();
+            self.auth_server = http_server.replacen("http://", "https://", 1);
+            let tls = self.path_exists(PATH_GET_CHALLENGE);
+            self.auth_server = http_server;
+            Some(tls)
+        } else {
+            // built without tls support, cannot tell
+            None
+        };
+
+        let paths = [
+            PATH_GET_CHALLENGE,
+            PATH_PORTAL,
+            PATH_USER_INFO,
+            PATH_DM,
+            &self.online_path,
+        ]
+        .into_iter()
+        .map(|path| (path.to_string(), self.path_exists(path)))
+        .collect();
+
+        Ok(ProbeReport {
+            srun_ver: challenge.srun_ver,
+            server_time: challenge.st,
+            time_skew,
+            jsonp: body.len() != resp.len(),
+            tls,
+            // scanning the portal scripts is left to --detect-encoding
+            enc_ver: self
+                .detect_encoding()
+                .ok()
+                .flatten()
+                .map(|profile| profile.enc_ver),
+            client_ip: challenge.client_ip,
+            user_info: self.user_info().ok(),
+            paths,
+        })
+    }
+
+    /// List the sessions currently online for this account.
+    pub fn online_devices(&self) -> Result<Vec<OnlineDevice>> {
+        let time = self.now().to_string();
+        let query = vec![
+            ("callback", "sdu"),
+            ("username", &self.username),
             ("_", &time),
         ];
+        let resp: OnlineDevicesResponse = self.get_jsonp(&self.online_path, query)?;
+        Ok(resp.list)
+    }
 
-        let result: PortalResponse = {
-            #[cfg(feature = "reqwest")]
-            {
-                let resp = req.query(&query).send()?.bytes()?;
-                serde_json::from_slice(&resp[4..resp.len() - 1])?
+    fn kick(&self) -> Result<()> {
+        let victims = kick_victims(
+            self.online_devices()?,
+            &[&self.client_ip, &self.ip, &self.ipv6],
+            self.kick.unwrap_or_default(),
+        );
+        if victims.is_empty() {
+            outln!("no other online device found");
+        }
+        for device in victims {
+            outln!("kick device: {:#?}", device);
+            // the device keeps its mac binding, it is only logged out
+            let result = self.drop_session(&device.ip, false)?;
+            if result.is_error() {
+                return Err(Box::new(result.to_error()));
             }
-            #[cfg(feature = "ureq")]
-            {
-                let resp = req.query_vec(query).call()?.into_string()?;
-                let resp = resp.as_bytes();
-                serde_json::from_slice(&resp[4..resp.len() - 1])?
+        }
+        Ok(())
+    }
+
+    fn drop_session(&self, ip: &str, unbind: bool) -> Result<PortalResponse> {
+        let time = self.now().to_string();
+        let unbind = if unbind { "1" } else { "0" };
+        let sign = {
+            let mut sha1_hasher = Sha1::new();
+            sha1_hasher.update([time.as_str(), &self.username, ip, unbind, &time].concat());
+            format!("{:x}", sha1_hasher.finalize())
+        };
+        let query = vec![
+            ("callback", "sdu"),
+            ("ip", ip),
+            ("username", &self.username),
+            ("time", &time),
+            ("unbind", unbind),
+            ("sign", &sign),
+        ];
+        let result: PortalResponse = self.get_jsonp(PATH_DM, query)?;
+        outln!("{:#?}", result);
+        Ok(result)
+    }
+
+    /// Log out, and the IPv6 session too if double stack is on.
+    pub fn logout(&mut self) -> Result<()> {
+        let (result, result6) = match self.end_sessions() {
+            Ok(results) => results,
+            Err(e) => (Err(e), None),
+        };
+        // without a second session the IPv6 one went with the first
+        let ended6 = match result6 {
+            Some(ref result6) => result6.is_ok(),
+            None => result.is_ok(),
+        };
+        self.record(|state| {
+            if result.is_ok() {
+                state.online_ip = None;
+                state.access_token = None;
+            }
+            if ended6 {
+                state.online_ipv6 = None;
+            }
+            state.last_error = result
+                .as_ref()
+                .err()
+                .or(result6.as_ref().and_then(|r| r.as_ref().err()))
+                .map(|e| e.to_string());
+        });
+        result.and(result6.unwrap_or(Ok(())))
+    }
+
+    // the results of the IPv4 session and, with double stack, the IPv6 one
+    fn end_sessions(&mut self) -> Result<(Result<()>, Option<Result<()>>)> {
+        if self.protocol == Protocol::Srun3k {
+            return Ok((self.logout_3k(), None));
+        }
+
+        if self.detect_ip {
+            self.detect_ip()?;
+        }
+
+        let result = self.end_session();
+        let Some(mut client) = self.v6_client() else {
+            return Ok((result, None));
+        };
+        // one family failing does not hide how the other one went
+        let result6 = client.end_session();
+        if let Err(ref e) = result {
+            eoutln!("ipv4 logout failed: {}", e);
+        }
+        if let Err(ref e) = result6 {
+            eoutln!("ipv6 logout failed: {}", e);
+        }
+        Ok((result, Some(result6)))
+    }
+
+    fn end_session(&mut self) -> Result<()> {
+        // also syncs the clock for the signed request
+        let challenge = self.get_challenge()?;
+        let mode = match self.logout_mode {
+            LogoutMode::Auto => {
+                if srun_ver_at_least(&challenge.srun_ver, SIGNED_LOGOUT_SINCE) {
+                    LogoutMode::Signed
+                } else {
+                    LogoutMode::Legacy
+                }
+            }
+            mode => mode,
+        };
+
+        let result = match mode {
+            LogoutMode::Signed => self.drop_session(&self.client_ip, false)?,
+            LogoutMode::Unbind => self.drop_session(&self.client_ip, true)?,
+            _ => {
+                let ac_id = self.acid.to_string();
+                let time = self.now().to_string();
+                let query = vec![
+                    ("callback", "sdu"),
+                    ("action", "logout"),
+                    ("username", &self.username),
+                    ("ip", &self.client_ip),
+                    ("ac_id", &ac_id),
+                    ("_", &time),
+                ];
+                let result: PortalResponse = self.get_jsonp(PATH_PORTAL, query)?;
+                outln!("{:#?}", result);
+                result
             }
         };
 
-        println!("{:#?}", result);
+        if result.is_error() {
+            return Err(Box::new(result.to_error()));
+        }
         Ok(())
     }
+
+    fn login_3k(&mut self) -> Result<()> {
+        let username = srun3k::encode_username(&self.username);
+        let password = srun3k::encode_password(&self.password);
+        let form = [
+            ("username", username.as_str()),
+            ("password", &password),
+            ("drop", "0"),
+            ("type", srun3k::PARAM_TYPE),
+            ("n", srun3k::PARAM_N),
+        ];
+
+        outln!("will try at most {} times...", self.retry_times);
+        let mut result = Err(SrunError::Portal(String::new()));
+        for ti in 1..=self.retry_times {
+            let resp = self.post_form(srun3k::PATH_LOGIN, &form)?;
+            result = srun3k::parse_login_response(&resp);
+            match result {
+                Ok(ref uid) => {
+                    outln!("try {}/{}: success, uid: {}", ti, self.retry_times, uid);
+                    break;
+                }
+                Err(ref e) => outln!("try {}/{}: failed, {}", ti, self.retry_times, e),
+            }
+            thread::sleep(Duration::from_millis(self.retry_delay as u64));
+        }
+        self.uid = result?;
+        Ok(())
+    }
+
+    fn logout_3k(&mut self) -> Result<()> {
+        let resp = if self.uid.is_empty() {
+            let username = srun3k::encode_username(&self.username);
+            let password = srun3k::encode_password(&self.password);
+            let form = [
+                ("username", username.as_str()),
+                ("password", &password),
+                ("drop", "0"),
+                ("type", srun3k::PARAM_TYPE),
+                ("n", srun3k::PARAM_N),
+            ];
+            self.post_form(srun3k::PATH_FORCE_LOGOUT, &form)?
+        } else {
+            self.post_form(srun3k::PATH_LOGOUT, &[("uid", &self.uid)])?
+        };
+        outln!("{}", resp);
+        srun3k::check_response(&resp)?;
+        self.uid.clear();
+        Ok(())
+    }
+
+    /// srun3k drops sessions that are not kept alive, call this periodically after login.
+    pub fn keep_alive(&self) -> Result<()> {
+        if self.protocol != Protocol::Srun3k {
+            return Ok(());
+        }
+        let resp = self.post_form(srun3k::PATH_KEEP_ALIVE, &[("uid", &self.uid)])?;
+        srun3k::check_response(&resp)?;
+        Ok(())
+    }
+
+    /// Log out every online session of the account, no ip needed.
+    pub fn logout_all(&mut self) -> Result<()> {
+        self.get_challenge()?;
+        let unbind = self.logout_mode == LogoutMode::Unbind;
+        let devices = self.online_devices()?;
+        if devices.is_empty() {
+            outln!("no online device found");
+        }
+        for device in devices {
+            outln!("logout device: {:#?}", device);
+            let result = self.drop_session(&device.ip, unbind)?;
+            if result.is_error() {
+                return Err(Box::new(result.to_error()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Addresses online after a login, by family.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Sessions {
+    pub ipv4: Option<String>,
+    pub ipv6: Option<String>,
+    /// The network was connected before, srun did not log in.
+    #[serde(skip)]
+    pub already_online: bool,
+}
+
+impl Sessions {
+    fn push(&mut self, ip: &str) {
+        match IpAddr::from_str(ip) {
+            Ok(IpAddr::V4(_)) => self.ipv4 = Some(ip.to_owned()),
+            Ok(IpAddr::V6(_)) => self.ipv6 = Some(ip.to_owned()),
+            Err(_) => {}
+        }
+    }
+}
+
+impl fmt::Display for Sessions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ip = |ip: &Option<String>| ip.clone().unwrap_or_else(|| "-".to_string());
+        write!(f, "ipv4 {}, ipv6 {}", ip(&self.ipv4), ip(&self.ipv6))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProbeReport {
+    pub srun_ver: String,
+    pub server_time: u64,
+    /// `server_time` minus local time, in seconds
+    pub time_skew: i64,
+    pub jsonp: bool,
+    /// `None` if srun is built without tls
+    pub tls: Option<bool>,
+    /// `enc_ver` found in the portal page or its scripts, see `detect_encoding`
+    pub enc_ver: Option<String>,
+    pub client_ip: String,
+    pub user_info: Option<UserInfo>,
+    /// portal paths and whether the server knows them
+    pub paths: Vec<(String, bool)>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct UserInfo {
+    pub error: String,
+    pub user_name: String,
+    pub online_ip: String,
+    pub add_time: u64,
+    pub sum_bytes: u64,
+    pub sum_seconds: u64,
+    pub remain_bytes: u64,
+    pub remain_seconds: u64,
+    pub user_balance: f64,
+    pub wallet_balance: f64,
+    pub sysver: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OnlineDevice {
+    pub ip: String,
+    #[serde(alias = "login_time")]
+    pub add_time: u64,
+    #[serde(alias = "user_mac")]
+    pub mac: String,
+    pub os_name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct OnlineDevicesResponse {
+    #[serde(alias = "rows", alias = "data")]
+    list: Vec<OnlineDevice>,
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 struct ChallengeResponse {
     challenge: Option<String>,
     client_ip: String,
@@ -421,14 +1331,14 @@ struct PortalResponse {
     client_ip: String,
     online_ip: String,
     real_name: String,
-    remain_flux: i32,
+    remain_flux: f64,
     remain_times: i32,
     res: String,
     srun_ver: String,
     suc_msg: String,
     sysver: String,
     username: String,
-    wallet_balance: i32,
+    wallet_balance: f64,
     st: u64,
 }
 
@@ -446,9 +1356,256 @@ impl Default for ECode {
     }
 }
 
+impl PortalResponse {
+    fn error_message(&self) -> &str {
+        if self.error_msg.is_empty() {
+            &self.error
+        } else {
+            &self.error_msg
+        }
+    }
+
+    fn is_error(&self) -> bool {
+        !matches!(self.error.as_str(), "" | "ok" | "logout_ok")
+    }
+
+    fn has_code(&self, code: &str) -> bool {
+        matches!(&self.ecode, ECode::S(c) if c == code) || self.error_message().starts_with(code)
+    }
+
+    fn has_words(&self, words: &[&str]) -> bool {
+        let msg = self.error_message().to_lowercase();
+        words.iter().any(|w| msg.contains(w))
+    }
+
+    fn has_message(&self, messages: &[&str]) -> bool {
+        let msg = self.error_message();
+        let msg = match msg.split_once(": ") {
+            Some((code, rest)) if code.starts_with('E') => rest,
+            _ => msg,
+        };
+        let msg = msg.trim().trim_end_matches(['.', '。']).to_lowercase();
+        messages.contains(&msg.as_str())
+    }
+
+    fn is_device_limit(&self) -> bool {
+        self.has_code(ECODE_DEVICE_LIMIT)
+    }
+
+    fn to_error(&self) -> SrunError {
+        let msg = self.error_message().to_owned();
+        if self.is_device_limit() {
+            SrunError::DeviceLimit(msg)
+        } else if self.has_code(ECODE_ARREARS) || self.has_words(ARREARS_WORDS) {
+            SrunError::Arrears(msg)
+        } else if self.has_message(QUOTA_MESSAGES) {
+            SrunError::Quota(msg)
+        } else {
+            SrunError::Portal(msg)
+        }
+    }
+}
+
+fn is_device_limit(e: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(
+        e.downcast_ref::<SrunError>(),
+        Some(SrunError::DeviceLimit(_))
+    )
+}
+
+/// The portal refused the account itself, another account may still log in.
+/// Network errors are not account errors.
+pub fn is_account_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(
+        e.downcast_ref::<SrunError>(),
+        Some(SrunError::DeviceLimit(_) | SrunError::Arrears(_) | SrunError::Quota(_))
+    )
+}
+
+// srun_ver looks like "SRunCGIAuthIntfSvr V1.18 B20190423"
+fn parse_srun_ver(srun_ver: &str) -> Option<(u32, u32)> {
+    let ver = srun_ver
+        .split_whitespace()
+        .find_map(|s| s.strip_prefix(['V', 'v']))?;
+    let mut parts = ver.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().unwrap_or("0").parse().ok()?;
+    Some((major, minor))
+}
+
+fn srun_ver_at_least(srun_ver: &str, min: (u32, u32)) -> bool {
+    parse_srun_ver(srun_ver).is_some_and(|v| v >= min)
+}
+
+// accepts both `callback({...})` and plain json bodies
+fn strip_jsonp(resp: &[u8]) -> &[u8] {
+    let start = resp.iter().position(|&c| c == b'(');
+    let end = resp.iter().rposition(|&c| c == b')');
+    match (start, end) {
+        (Some(start), Some(end)) if start < end && !resp[..start].contains(&b'{') => {
+            &resp[start + 1..end]
+        }
+        _ => resp,
+    }
+}
+
+// http://[2001:db8::1]:8080/ has a bracketed IPv6 literal as host
+// the host of `url` if it is an IP literal
+fn url_ip(url: &str) -> Option<IpAddr> {
+    let host = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .split('/')
+        .next()?;
+    match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next()?.parse().ok(),
+        None => host.split(':').next()?.parse().ok(),
+    }
+}
+
+fn is_ipv6_url(url: &str) -> bool {
+    url.split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .starts_with('[')
+}
+
 fn unix_second() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("time went backwards")
         .as_secs()
 }
+
+// the other sessions `policy` picks, `own_ips` are the addresses of this client
+fn kick_victims(
+    devices: Vec<OnlineDevice>,
+    own_ips: &[&str],
+    policy: KickPolicy,
+) -> Vec<OnlineDevice> {
+    let mut devices: Vec<OnlineDevice> = devices
+        .into_iter()
+        .filter(|d| !own_ips.contains(&d.ip.as_str()))
+        .collect();
+    devices.sort_by_key(|d| d.add_time);
+    match policy {
+        KickPolicy::Oldest => devices.into_iter().take(1).collect(),
+        KickPolicy::Newest => devices.pop().into_iter().collect(),
+        KickPolicy::All => devices,
+    }
+}
+
+#[test]
+fn test_parse_srun_ver() {
+    assert_eq!(
+        parse_srun_ver("SRunCGIAuthIntfSvr V1.18 B20190423"),
+        Some((1, 18))
+    );
+    assert_eq!(parse_srun_ver("SRunCGIAuthIntfSvr"), None);
+}
+
+#[test]
+fn test_kick_victims() {
+    let devices = vec![
+        OnlineDevice {
+            ip: "10.0.0.2".to_owned(),
+            add_time: 300,
+            ..Default::default()
+        },
+        OnlineDevice {
+            ip: "10.0.0.3".to_owned(),
+            add_time: 100,
+            ..Default::default()
+        },
+        OnlineDevice {
+            ip: "10.0.0.4".to_owned(),
+            add_time: 50,
+            ..Default::default()
+        },
+    ];
+    // the session logging in is never kicked, even if it is the oldest
+    let victims = kick_victims(devices.clone(), &["10.0.0.4"], KickPolicy::Oldest);
+    assert_eq!(victims.len(), 1);
+    assert_eq!(victims[0].ip, "10.0.0.3");
+    let victims = kick_victims(devices.clone(), &["10.0.0.9"], KickPolicy::Oldest);
+    assert_eq!(victims.len(), 1);
+    assert_eq!(victims[0].ip, "10.0.0.4");
+    let victims = kick_victims(devices.clone(), &["10.0.0.9"], KickPolicy::Newest);
+    assert_eq!(victims.len(), 1);
+    assert_eq!(victims[0].ip, "10.0.0.2");
+    // both sessions of a double stack client are spared
+    let victims = kick_victims(devices, &["10.0.0.2", "10.0.0.3"], KickPolicy::All);
+    assert_eq!(victims.len(), 1);
+    assert_eq!(victims[0].ip, "10.0.0.4");
+}
+
+#[test]
+fn test_strip_jsonp() {
+    assert_eq!(strip_jsonp(br#"sdu({"res":"ok"})"#), br#"{"res":"ok"}"#);
+    assert_eq!(strip_jsonp(br#"{"res":"(ok)"}"#), br#"{"res":"(ok)"}"#);
+}
+
+#[test]
+fn test_is_ipv6_url() {
+    assert!(is_ipv6_url("http://[2001:db8::1]"));
+    assert!(is_ipv6_url("https://[2001:db8::1]:8443/"));
+    assert!(!is_ipv6_url("http://10.0.0.1"));
+    assert!(!is_ipv6_url("http://portal.example.edu"));
+    assert_eq!(
+        url_ip("https://[2001:db8::1]:8443/"),
+        "2001:db8::1".parse().ok()
+    );
+    assert_eq!(url_ip("http://10.0.0.1:8080"), "10.0.0.1".parse().ok());
+    assert_eq!(url_ip("http://portal.example.edu"), None);
+}
+
+#[test]
+fn test_portal_error() {
+    let resp = PortalResponse {
+        ecode: ECode::S("E2620".to_owned()),
+        error: "login_error".to_owned(),
+        ..Default::default()
+    };
+    assert!(matches!(resp.to_error(), SrunError::DeviceLimit(_)));
+    let resp = PortalResponse {
+        ecode: ECode::S("E2616".to_owned()),
+        error: "login_error".to_owned(),
+        ..Default::default()
+    };
+    assert!(matches!(resp.to_error(), SrunError::Arrears(_)));
+    let resp = PortalResponse {
+        ecode: ECode::S("".to_owned()),
+        error: "login_error".to_owned(),
+        error_msg: "E2616: Arrearage users.".to_owned(),
+        ..Default::default()
+    };
+    assert!(matches!(resp.to_error(), SrunError::Arrears(_)));
+    let resp = PortalResponse {
+        ecode: ECode::S("".to_owned()),
+        error: "login_error".to_owned(),
+        error_msg: "E2614: Flux limit exceeded.".to_owned(),
+        ..Default::default()
+    };
+    assert!(matches!(resp.to_error(), SrunError::Quota(_)));
+    let resp = PortalResponse {
+        ecode: ECode::S("".to_owned()),
+        error: "login_error".to_owned(),
+        error_msg: "流量已用完".to_owned(),
+        ..Default::default()
+    };
+    assert!(matches!(resp.to_error(), SrunError::Quota(_)));
+    let resp = PortalResponse {
+        ecode: ECode::S("".to_owned()),
+        error: "login_error".to_owned(),
+        error_msg: "Invalid flux parameter".to_owned(),
+        ..Default::default()
+    };
+    assert!(matches!(resp.to_error(), SrunError::Portal(_)));
+    let resp = PortalResponse {
+        ecode: ECode::S("".to_owned()),
+        error: "login_error".to_owned(),
+        error_msg: "E2553: Password is error.".to_owned(),
+        ..Default::default()
+    };
+    assert!(matches!(resp.to_error(), SrunError::Portal(_)));
+    assert!(!is_account_error(&std::io::Error::other("timed out")));
+}
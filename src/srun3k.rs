@@ -0,0 +1,69 @@
+use crate::srun::SrunError;
+
+pub(crate) const PATH_LOGIN: &str = "/cgi-bin/do_login";
+pub(crate) const PATH_LOGOUT: &str = "/cgi-bin/do_logout";
+pub(crate) const PATH_FORCE_LOGOUT: &str = "/cgi-bin/force_logout";
+pub(crate) const PATH_KEEP_ALIVE: &str = "/cgi-bin/keeplive";
+
+// srun3k clients always send these
+pub(crate) const PARAM_N: &str = "100";
+pub(crate) const PARAM_TYPE: &str = "1";
+
+const PASSWORD_KEY: &[u8] = b"1234567890";
+
+pub(crate) fn encode_username(username: &str) -> String {
+    let encoded: String = username
+        .bytes()
+        .map(|b| b.wrapping_add(4) as char)
+        .collect();
+    format!("{{SRUN3}}\r\n{}", encoded)
+}
+
+pub(crate) fn encode_password(password: &str) -> String {
+    let mut encoded = String::with_capacity(password.len() * 2);
+    for (i, c) in password.bytes().enumerate() {
+        let k = c ^ PASSWORD_KEY[PASSWORD_KEY.len() - i % PASSWORD_KEY.len() - 1];
+        let l = ((k & 0x0f) + 0x36) as char;
+        let h = ((k >> 4 & 0x0f) + 0x63) as char;
+        if i % 2 == 0 {
+            encoded.push(l);
+            encoded.push(h);
+        } else {
+            encoded.push(h);
+            encoded.push(l);
+        }
+    }
+    encoded
+}
+
+// a successful login answers with the numeric session uid
+pub(crate) fn parse_login_response(resp: &str) -> Result<String, SrunError> {
+    let resp = resp.trim();
+    if !resp.is_empty() && resp.bytes().all(|c| c.is_ascii_digit()) {
+        return Ok(resp.to_owned());
+    }
+    Err(match resp {
+        "online_num_error" | "usernum_error" => SrunError::DeviceLimit(resp.to_owned()),
+        _ => SrunError::Portal(resp.to_owned()),
+    })
+}
+
+// failures are a bare `error` or an `*_error` code like `not_online_error`
+pub(crate) fn check_response(resp: &str) -> Result<(), SrunError> {
+    let resp = resp.trim();
+    if resp == "error" || resp.ends_with("_error") {
+        Err(SrunError::Portal(resp.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_encode() {
+    assert_eq!(encode_username("abc"), "{SRUN3}\r\nefg");
+    assert_eq!(encode_password("123456"), "7ccAAcc99cc9");
+    // used to overflow, `ü` as u8 is 252
+    assert_eq!(encode_username("ü"), "{SRUN3}\r\n\u{c7}\u{c0}");
+    assert!(check_response("logout_ok").is_ok());
+    assert!(check_response("not_online_error").is_err());
+}
@@ -0,0 +1,94 @@
+use crate::{Result, create_private_dir, lock_private_file, open_private_file, state_home};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// What srun last knew about a user on a dial, kept in the state directory
+/// between runs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserState {
+    /// Unix time of the last successful login.
+    pub login_time: Option<u64>,
+    pub online_ip: Option<String>,
+    pub online_ipv6: Option<String>,
+    pub access_token: Option<String>,
+    pub server_version: Option<String>,
+    pub last_error: Option<String>,
+}
+
+impl UserState {
+    /// `users` in the [`state_home`].
+    pub fn default_dir() -> PathBuf {
+        state_home().join("users")
+    }
+
+    /// The state of `label` dialed on `dial`, an interface or IP, one account
+    /// may be online on several.
+    pub fn key(label: &str, dial: Option<&str>) -> String {
+        match dial {
+            Some(dial) if !dial.is_empty() => format!("{}@{}", label, dial),
+            _ => label.to_owned(),
+        }
+    }
+
+    // keys may hold anything, only some of it is safe in a file name
+    fn name(key: &str) -> String {
+        key.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "-_.@".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    /// The state of `key`, empty if there is none yet.
+    pub fn load(dir: &Path, key: &str) -> Result<Self> {
+        let path = dir.join(format!("{}.json", Self::name(key)));
+        match fs::read(path) {
+            Ok(buf) => Ok(serde_json::from_slice(&buf)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Change the state of `key`, other sruns wait for it on the same key.
+    pub fn update(dir: &Path, key: &str, f: impl FnOnce(&mut Self)) -> Result<()> {
+        create_private_dir(dir)?;
+        let name = Self::name(key);
+        let _lock = lock_private_file(&dir.join(format!(".{}.lock", name)))?;
+        let mut state = Self::load(dir, key)?;
+        f(&mut state);
+        // readers never see half a file, only the lock holder writes the temp one
+        let tmp = dir.join(format!(".{}.tmp", name));
+        let mut file = open_private_file(&tmp)?;
+        file.set_len(0)?;
+        file.write_all(serde_json::to_string_pretty(&state)?.as_bytes())?;
+        fs::rename(tmp, dir.join(format!("{}.json", name)))?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_user_state() {
+    let dir = std::env::temp_dir().join(format!("srun-state-test-{}", std::process::id()));
+    assert_eq!(UserState::load(&dir, "a/b").unwrap(), UserState::default());
+    UserState::update(&dir, "a/b", |state| {
+        state.online_ip = Some("10.0.0.2".to_owned());
+    })
+    .unwrap();
+    assert!(dir.join("a_b.json").exists());
+    let state = UserState::load(&dir, "a/b").unwrap();
+    assert_eq!(state.online_ip.as_deref(), Some("10.0.0.2"));
+    let key = UserState::key("a/b", Some("eth0"));
+    assert_eq!(UserState::load(&dir, &key).unwrap(), UserState::default());
+    UserState::update(&dir, &key, |state| state.online_ip = None).unwrap();
+    assert!(dir.join("a_b@eth0.json").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
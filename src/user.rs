@@ -1,11 +1,35 @@
-use serde::Deserialize;
+use crate::{Cidr, DevicePreset, IpSelector, srun::SrunError};
+use serde::{Deserialize, Deserializer};
+use std::{fmt, str::FromStr};
+
+/// Default way to combine username and operator code, e.g. `202112345@cmcc`.
+pub const DEFAULT_USERNAME_TEMPLATE: &str = "{username}@{operator}";
 
 #[derive(Debug, Default, Deserialize, Clone)]
 pub struct User {
+    /// Short name for `--user`, the username if not set.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// `false` leaves the user out, unless it is picked by `--user`.
+    pub enabled: Option<bool>,
     pub username: String,
     pub password: String,
     pub ip: Option<String>,
+    /// Address of the IPv6 session, from `if_name` if not set.
+    pub ipv6: Option<String>,
     pub if_name: Option<String>,
+    /// Local addresses in this network belong to the user, for `login --all-interfaces`.
+    pub subnet: Option<Cidr>,
+    pub device: Option<DevicePreset>,
+    pub operator: Option<Operator>,
+    /// `SO_MARK` of the sockets, to pick the uplink by `ip rule fwmark`.
+    pub fwmark: Option<u32>,
+    /// Named network namespace, as in `ip netns exec`.
+    pub netns: Option<String>,
+    /// Rules picking the IP of this user, they win over the config's.
+    #[serde(flatten)]
+    pub select: IpSelector,
 }
 
 impl User {
@@ -14,7 +38,7 @@ impl User {
             username,
             password,
             ip: Some(ip),
-            if_name: None,
+            ..Default::default()
         }
     }
 
@@ -22,8 +46,187 @@ impl User {
         Self {
             username,
             password,
-            ip: None,
             if_name: Some(if_name),
+            ..Default::default()
         }
     }
+
+    /// `name`, or the username if it has none.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.username)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    fn is_called(&self, name: &str) -> bool {
+        self.name.as_deref() == Some(name) || self.username == name
+    }
+
+    /// Username sent to the portal, with the operator code filled into `template`.
+    pub fn full_username(&self, template: &str) -> String {
+        match self.operator {
+            Some(operator) => template
+                .replace("{username}", &self.username)
+                .replace("{operator}", operator.code()),
+            None => self.username.clone(),
+        }
+    }
+}
+
+/// Picks the users of a config to act on.
+#[derive(Debug, Default, Clone)]
+pub struct UserFilter {
+    /// Names or usernames.
+    pub users: Vec<String>,
+    pub tags: Vec<String>,
+    /// Names, usernames or tags to leave out.
+    pub exclude: Vec<String>,
+}
+
+impl UserFilter {
+    /// With no `users` and `tags` every enabled user is picked, `exclude` wins over both.
+    pub fn matches(&self, user: &User) -> bool {
+        if self
+            .exclude
+            .iter()
+            .any(|e| user.is_called(e) || user.tags.contains(e))
+        {
+            return false;
+        }
+        if self.users.iter().any(|name| user.is_called(name)) {
+            return true;
+        }
+        if !user.is_enabled() {
+            return false;
+        }
+        self.tags.is_empty() && self.users.is_empty()
+            || self.tags.iter().any(|tag| user.tags.contains(tag))
+    }
+}
+
+/// Network operator selected by a suffix on the username.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Telecom,
+    Mobile,
+    Unicom,
+    Campus,
+}
+
+impl Operator {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Telecom => "ctcc",
+            Self::Mobile => "cmcc",
+            Self::Unicom => "unicom",
+            Self::Campus => "xn",
+        }
+    }
+
+    /// Read the operator back from a username like `202112345@cmcc`.
+    pub fn from_username(username: &str) -> Option<Self> {
+        let (_, code) = username.rsplit_once('@')?;
+        code.parse().ok()
+    }
+}
+
+impl FromStr for Operator {
+    type Err = SrunError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ctcc" | "chinanet" | "telecom" => Ok(Self::Telecom),
+            "cmcc" | "mobile" => Ok(Self::Mobile),
+            "unicom" | "cucc" => Ok(Self::Unicom),
+            "xn" | "campus" => Ok(Self::Campus),
+            _ => Err(SrunError::InvalidValue("operator", s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Operator {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[test]
+fn test_full_username() {
+    let mut user = User::new("202112345".into(), String::new(), String::new());
+    assert_eq!(user.full_username(DEFAULT_USERNAME_TEMPLATE), "202112345");
+    user.operator = Some("chinanet".parse().unwrap());
+    assert_eq!(
+        user.full_username(DEFAULT_USERNAME_TEMPLATE),
+        "202112345@ctcc"
+    );
+    assert_eq!(
+        user.full_username("{operator}-{username}"),
+        "ctcc-202112345"
+    );
+    assert_eq!(
+        Operator::from_username("202112345@cucc"),
+        Some(Operator::Unicom)
+    );
+}
+
+#[test]
+fn test_user_filter() {
+    let users = [
+        User {
+            name: Some("a".to_owned()),
+            username: "a-id".to_owned(),
+            tags: vec!["lab".to_owned()],
+            ..Default::default()
+        },
+        User {
+            name: Some("b".to_owned()),
+            username: "b-id".to_owned(),
+            tags: vec!["lab".to_owned(), "backup".to_owned()],
+            ..Default::default()
+        },
+        User {
+            name: Some("c".to_owned()),
+            username: "c-id".to_owned(),
+            enabled: Some(false),
+            ..Default::default()
+        },
+    ];
+    let filter = UserFilter::default();
+    let picked: Vec<&str> = users
+        .iter()
+        .filter(|u| filter.matches(u))
+        .map(User::label)
+        .collect();
+    assert_eq!(picked, ["a", "b"]);
+    let filter = UserFilter {
+        tags: vec!["lab".to_owned()],
+        exclude: vec!["backup".to_owned()],
+        ..Default::default()
+    };
+    let picked: Vec<&str> = users
+        .iter()
+        .filter(|u| filter.matches(u))
+        .map(User::label)
+        .collect();
+    assert_eq!(picked, ["a"]);
+    let filter = UserFilter {
+        users: vec!["c".to_owned(), "b-id".to_owned()],
+        ..Default::default()
+    };
+    let picked: Vec<&str> = users
+        .iter()
+        .filter(|u| filter.matches(u))
+        .map(User::label)
+        .collect();
+    assert_eq!(picked, ["b", "c"]);
 }
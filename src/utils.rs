@@ -1,9 +1,9 @@
-use crate::Result;
+use crate::{IfMatcher, Interface, Result, User, http_client::BindConnector, interfaces};
 use quick_error::quick_error;
 use std::{
-    io,
-    net::{IpAddr, TcpStream, ToSocketAddrs},
-    process::exit,
+    env, fs, io,
+    net::{IpAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
     time::{Duration, SystemTime},
 };
 
@@ -14,49 +14,180 @@ quick_error! {
     }
 }
 
-pub fn tcp_ping(addr: &str) -> Result<u16> {
-    let addr = addr.to_socket_addrs()?.next();
+pub(crate) fn tcp_ping(connector: &BindConnector, addr: &str, timeout: Duration) -> Result<u16> {
+    let addr = addr
+        .to_socket_addrs()?
+        .find(|addr| connector.can_reach(addr));
     if addr.is_none() {
         return Err(Box::new(UtilError::AddrResolveError));
     }
-    let timeout = Duration::from_secs(3);
     let start_time = SystemTime::now();
-    let stream = TcpStream::connect_timeout(&addr.unwrap(), timeout)?;
+    let stream = connector.connect_timeout(&addr.unwrap(), timeout)?;
     stream.peer_addr()?;
     let d = SystemTime::now().duration_since(start_time)?;
     Ok(d.as_millis() as u16)
 }
 
-fn get_ifs() -> Vec<(String, IpAddr)> {
-    let ifs = match if_addrs::get_if_addrs() {
-        Ok(ifs) => ifs,
-        Err(err) => {
-            println!("Get Net Intercafes failed: {err}");
-            exit(500)
-        }
-    };
-
-    let mut ips = Vec::with_capacity(ifs.len());
-    for i in ifs {
-        if !i.is_loopback() {
-            ips.push((i.name.clone(), i.ip()))
-        }
+/// `srun` in `$XDG_STATE_HOME`, else in `/var/lib` for root and in
+/// `~/.local/state` for everyone else.
+pub fn state_home() -> PathBuf {
+    let non_empty = |dir: &std::ffi::OsString| !dir.is_empty();
+    if let Some(dir) = env::var_os("XDG_STATE_HOME").filter(non_empty) {
+        return PathBuf::from(dir).join("srun");
+    }
+    match env::var_os("HOME").filter(non_empty) {
+        Some(home) if !is_root() => PathBuf::from(home).join(".local/state/srun"),
+        _ => PathBuf::from("/var/lib/srun"),
     }
-    ips
 }
 
+#[cfg(target_os = "linux")]
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_root() -> bool {
+    false
+}
+
+/// Create `dir` and its parents, only the owner may look inside the new ones.
+pub fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Open or create `path`, only the owner may read a new one. A link planted
+/// in its place is not followed.
+pub fn open_private_file(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.read(true).write(true).create(true).truncate(false);
+    #[cfg(target_os = "linux")]
+    std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NOFOLLOW);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// [`open_private_file`], locked until the file is dropped.
+pub fn lock_private_file(path: &Path) -> io::Result<fs::File> {
+    let file = open_private_file(path)?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// IP of the interface matched by `if_name`, see [`IfMatcher`] for the syntax.
 pub fn get_ip_by_if_name(if_name: &str) -> Option<String> {
-    let ifs = get_ifs();
-    for i in ifs {
-        if i.0.contains(if_name) && i.1.is_ipv4() {
-            return Some(i.1.to_string());
+    let matcher: IfMatcher = if_name.parse().ok()?;
+    let ifs = interfaces().ok()?;
+    matcher
+        .find(&ifs)
+        .iter()
+        .find_map(Interface::auth_ip)
+        .map(|ip| ip.to_string())
+}
+
+/// Name of the interface matched by `if_name`, the one its IP comes from.
+pub fn get_if_name(if_name: &str) -> Option<String> {
+    let matcher: IfMatcher = if_name.parse().ok()?;
+    if let IfMatcher::Exact(name) = matcher {
+        return Some(name);
+    }
+    let ifs = interfaces().ok()?;
+    let found = matcher.find(&ifs);
+    found
+        .iter()
+        .find(|i| i.auth_ip().is_some())
+        .or(found.first())
+        .map(|i| i.name.clone())
+}
+
+/// Global IPv6 of the interface matched by `if_name`, for the double stack session.
+pub fn get_ipv6_by_if_name(if_name: &str) -> Option<String> {
+    let matcher: IfMatcher = if_name.parse().ok()?;
+    let ifs = interfaces().ok()?;
+    matcher
+        .find(&ifs)
+        .iter()
+        .find_map(Interface::ipv6)
+        .map(|ip| ip.to_string())
+}
+
+/// A local address and the config user that logs it in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserAddr {
+    pub if_name: String,
+    pub ip: IpAddr,
+    /// Index into the users.
+    pub user: usize,
+}
+
+/// Every local address paired with the user it belongs to, see [`match_users`].
+pub fn user_addrs(users: &[User]) -> Result<Vec<UserAddr>> {
+    Ok(match_users(&interfaces()?, users))
+}
+
+/// Pair each address with the first user whose `subnet` contains it and whose
+/// `if_name` matches its interface, either one may be left out. Like
+/// `get_ip_by_if_name`, an `if_name` alone stands for the IPv4 addresses,
+/// IPv6 only if the interface has none. Users in a `netns` never match.
+pub fn match_users(ifs: &[Interface], users: &[User]) -> Vec<UserAddr> {
+    let matchers: Vec<Option<IfMatcher>> = users
+        .iter()
+        .map(|user| user.if_name.as_deref().and_then(|n| n.parse().ok()))
+        .collect();
+    let mut matched = Vec::new();
+    for iface in ifs.iter().filter(|i| !i.is_loopback()) {
+        let has_v4 = iface.addrs.iter().any(|a| a.ip.is_ipv4());
+        for ip in iface.addrs.iter().map(|a| a.ip) {
+            if ip.is_loopback() || matches!(ip, IpAddr::V6(v6) if v6.is_unicast_link_local()) {
+                continue;
+            }
+            let user = users.iter().zip(&matchers).position(|(user, matcher)| {
+                if user.netns.is_some() {
+                    return false;
+                }
+                match (user.subnet, matcher) {
+                    (Some(subnet), Some(matcher)) => subnet.contains(&ip) && matcher.matches(iface),
+                    (Some(subnet), None) => subnet.contains(&ip),
+                    (None, Some(matcher)) => (ip.is_ipv4() || !has_v4) && matcher.matches(iface),
+                    (None, None) => false,
+                }
+            });
+            if let Some(user) = user {
+                matched.push(UserAddr {
+                    if_name: iface.name.clone(),
+                    ip,
+                    user,
+                });
+            }
         }
     }
-    None
+    matched
 }
 
 pub fn select_ip() -> Option<String> {
-    let ips = get_ifs();
+    let ifs = match interfaces() {
+        Ok(ifs) => ifs,
+        Err(err) => {
+            println!("Get Net Interfaces failed: {err}");
+            return None;
+        }
+    };
+    let ips: Vec<(String, IpAddr)> = ifs
+        .into_iter()
+        .filter(|i| !i.is_loopback())
+        .flat_map(|i| {
+            let name = i.name;
+            i.addrs
+                .into_iter()
+                .filter(|a| !a.ip.is_loopback())
+                .map(move |a| (name.clone(), a.ip))
+        })
+        .collect();
     if ips.is_empty() {
         return None;
     }
@@ -75,12 +206,13 @@ pub fn select_ip() -> Option<String> {
             .read_line(&mut input_text)
             .expect("failed to read from stdin");
         let trimmed = input_text.trim();
-        if let Ok(i) = trimmed.parse::<usize>() {
-            if i > 0 && i <= ips.len() {
-                let ip = ips[i - 1].1.to_string();
-                println!("you choose {}", ip);
-                return Some(ip);
-            }
+        if let Ok(i) = trimmed.parse::<usize>()
+            && i > 0
+            && i <= ips.len()
+        {
+            let ip = ips[i - 1].1.to_string();
+            println!("you choose {}", ip);
+            return Some(ip);
         }
         println!("not a valid index number, {}/3", t);
     }
@@ -100,6 +232,96 @@ fn test_get_ip_by_name() {
 
 #[test]
 fn test_tcp_ping() {
-    let p = tcp_ping("baidu.com:80");
+    let p = tcp_ping(
+        &BindConnector::default(),
+        "baidu.com:80",
+        Duration::from_secs(3),
+    );
     println!("{:?}", p);
 }
+
+#[test]
+fn test_match_users() {
+    use crate::IfAddr;
+
+    let ifs = [
+        Interface {
+            name: "lo".to_owned(),
+            index: 0,
+            mac: None,
+            addrs: vec![IfAddr {
+                ip: "127.0.0.1".parse().unwrap(),
+                prefix: 24,
+            }],
+            up: true,
+            running: true,
+        },
+        Interface {
+            name: "eth0".to_owned(),
+            index: 0,
+            mac: None,
+            addrs: vec![
+                IfAddr {
+                    ip: "10.0.0.2".parse().unwrap(),
+                    prefix: 24,
+                },
+                IfAddr {
+                    ip: "2001:db8::2".parse().unwrap(),
+                    prefix: 64,
+                },
+                IfAddr {
+                    ip: "fe80::1".parse().unwrap(),
+                    prefix: 64,
+                },
+            ],
+            up: true,
+            running: true,
+        },
+        Interface {
+            name: "wan1".to_owned(),
+            index: 0,
+            mac: None,
+            addrs: vec![IfAddr {
+                ip: "172.16.0.2".parse().unwrap(),
+                prefix: 24,
+            }],
+            up: true,
+            running: true,
+        },
+        Interface {
+            name: "wan2".to_owned(),
+            index: 0,
+            mac: None,
+            addrs: vec![IfAddr {
+                ip: "192.168.1.2".parse().unwrap(),
+                prefix: 24,
+            }],
+            up: true,
+            running: true,
+        },
+    ];
+    let users = [
+        User {
+            if_name: Some("eth*".to_owned()),
+            ..Default::default()
+        },
+        User {
+            subnet: Some("192.168.0.0/16".parse().unwrap()),
+            ..Default::default()
+        },
+        User {
+            if_name: Some("wan1".to_owned()),
+            netns: Some("dial1".to_owned()),
+            ..Default::default()
+        },
+    ];
+    let matched: Vec<(String, String, usize)> = match_users(&ifs, &users)
+        .into_iter()
+        .map(|m| (m.if_name, m.ip.to_string(), m.user))
+        .collect();
+    let expected = [("eth0", "10.0.0.2", 0), ("wan2", "192.168.1.2", 1)];
+    assert_eq!(
+        matched,
+        expected.map(|(n, ip, u)| (n.to_owned(), ip.to_owned(), u))
+    );
+}
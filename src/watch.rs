@@ -0,0 +1,93 @@
+use crate::Result;
+use std::{net::IpAddr, time::Duration};
+
+/// An address was added to or removed from an interface.
+#[derive(Debug, Clone)]
+pub struct AddrChange {
+    pub if_name: String,
+    pub ip: IpAddr,
+    pub added: bool,
+}
+
+/// Subscribes to the kernel's IPv4 and IPv6 address events.
+pub struct AddrWatcher {
+    #[cfg(target_os = "linux")]
+    netlink: crate::netlink::Netlink,
+}
+
+#[cfg(target_os = "linux")]
+impl AddrWatcher {
+    pub fn new() -> Result<Self> {
+        let groups = (libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        Ok(Self {
+            netlink: crate::netlink::Netlink::open(groups)?,
+        })
+    }
+
+    /// Wait for the next batch of changes, empty if `timeout` passed first.
+    pub fn next(&mut self, timeout: Option<Duration>) -> Result<Vec<AddrChange>> {
+        // a zero timeout would block forever
+        let timeout = timeout.map_or(Duration::ZERO, |t| t.max(Duration::from_millis(1)));
+        self.netlink.set_timeout(timeout)?;
+        let msgs = match self.netlink.recv() {
+            Ok(msgs) => msgs,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(Vec::new()),
+            Err(e) => return Err(Box::new(e)),
+        };
+        Ok(msgs
+            .iter()
+            .filter(|m| m.ty == libc::RTM_NEWADDR || m.ty == libc::RTM_DELADDR)
+            .filter_map(|m| parse_addr(m.ty == libc::RTM_NEWADDR, &m.payload))
+            .collect())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl AddrWatcher {
+    pub fn new() -> Result<Self> {
+        Err(Box::new(crate::SrunError::Unsupported(
+            "watching addresses on this platform",
+        )))
+    }
+
+    pub fn next(&mut self, _timeout: Option<Duration>) -> Result<Vec<AddrChange>> {
+        Ok(Vec::new())
+    }
+}
+
+// struct ifaddrmsg followed by IFA_* attributes
+#[cfg(target_os = "linux")]
+fn parse_addr(added: bool, payload: &[u8]) -> Option<AddrChange> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    if payload.len() < 8 {
+        return None;
+    }
+    let index = u32::from_ne_bytes(payload[4..8].try_into().ok()?);
+    let mut ip = None;
+    for (ty, data) in crate::netlink::attrs(&payload[8..]) {
+        let addr = match data.len() {
+            4 => IpAddr::from(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?)),
+            16 => IpAddr::from(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?)),
+            _ => continue,
+        };
+        // IFA_LOCAL is the interface's own address on point to point links
+        match ty {
+            libc::IFA_LOCAL => ip = Some(addr),
+            libc::IFA_ADDRESS if ip.is_none() => ip = Some(addr),
+            _ => {}
+        }
+    }
+    let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+    if unsafe { libc::if_indextoname(index, name.as_mut_ptr()) }.is_null() {
+        return None;
+    }
+    let if_name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+    Some(AddrChange {
+        if_name,
+        ip: ip?,
+        added,
+    })
+}
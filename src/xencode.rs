@@ -1,10 +1,14 @@
+use crate::Result;
 use base64::{
     Engine,
     alphabet::Alphabet,
     engine::{self, GeneralPurpose},
 };
 use lazy_static::lazy_static;
+use serde::Deserialize;
 
+const ENC_VER: &str = "srun_bx1";
+const ENC_PREFIX: &str = "{SRBX1}";
 const BASE64_ALPHABET: &str = "LVoJPiCN2R8G90yg+hmFHuacZ1OWMnrsSTXkYpUq/3dlbfKwv6xztjI7DeBE45QA";
 lazy_static! {
     static ref BASE64_ENGINE: GeneralPurpose = {
@@ -13,6 +17,95 @@ lazy_static! {
     };
 }
 
+/// How `password` is sent in the login request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordMode {
+    /// `{MD5}` followed by the HMAC-MD5 of the password keyed by the challenge.
+    #[default]
+    HmacMd5,
+    Plain,
+}
+
+/// Encoding of the `info` parameter, vendor customised portals change these.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct EncodingProfile {
+    pub enc_ver: String,
+    pub prefix: String,
+    pub alphabet: String,
+    pub password: PasswordMode,
+}
+
+impl Default for EncodingProfile {
+    fn default() -> Self {
+        Self {
+            enc_ver: ENC_VER.to_string(),
+            prefix: ENC_PREFIX.to_string(),
+            alphabet: BASE64_ALPHABET.to_string(),
+            password: PasswordMode::default(),
+        }
+    }
+}
+
+impl EncodingProfile {
+    fn engine(&self) -> Result<GeneralPurpose> {
+        if self.alphabet == BASE64_ALPHABET {
+            return Ok(BASE64_ENGINE.clone());
+        }
+        let alphabet = Alphabet::new(&self.alphabet)?;
+        Ok(GeneralPurpose::new(
+            &alphabet,
+            engine::GeneralPurposeConfig::new(),
+        ))
+    }
+
+    /// Guess the profile from portal html or javascript, `None` if nothing looks like one.
+    pub fn scan(text: &str) -> Option<Self> {
+        let enc_ver = scan_value(text, "enc_ver");
+        let alphabet = quoted_strings(text).find(|s| is_alphabet(s));
+        if enc_ver.is_none() && alphabet.is_none() {
+            return None;
+        }
+        let mut profile = Self::default();
+        if let Some(enc_ver) = enc_ver {
+            profile.enc_ver = enc_ver.to_string();
+            // srun derives the prefix from the version, srun_bx1 -> {SRBX1}
+            if let Some(ver) = enc_ver.strip_prefix("srun_") {
+                profile.prefix = format!("{{SR{}}}", ver.to_uppercase());
+            }
+        }
+        if let Some(alphabet) = alphabet {
+            profile.alphabet = alphabet.to_string();
+        }
+        Some(profile)
+    }
+}
+
+// value of `key: "value"` or `key = 'value'`
+fn scan_value<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    text.match_indices(key).find_map(|(i, _)| {
+        let rest = text[i + key.len()..].trim_start_matches(['"', '\'', ' ']);
+        let rest = rest.strip_prefix([':', '='])?.trim_start();
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &rest[1..];
+        let end = value.find(quote)?;
+        Some(&value[..end]).filter(|v| !v.is_empty())
+    })
+}
+
+fn quoted_strings(text: &str) -> impl Iterator<Item = &str> {
+    text.split(['"', '\'']).skip(1).step_by(2)
+}
+
+// a 64 char permutation that is not one of the well known alphabets
+fn is_alphabet(s: &str) -> bool {
+    s.len() == 64
+        && s != base64::alphabet::STANDARD.as_str()
+        && s != base64::alphabet::URL_SAFE.as_str()
+        && Alphabet::new(s).is_ok()
+}
+
 fn mix(buffer: &[u8], append_size: bool) -> Vec<u32> {
     let mut res: Vec<u32> = buffer
         .chunks(4)
@@ -77,14 +170,43 @@ fn x_encode(msg: &str, key: &str) -> Vec<u8> {
 }
 
 pub fn param_i(username: &str, password: &str, ip: &str, acid: i32, token: &str) -> String {
+    param_i_with(
+        &EncodingProfile::default(),
+        username,
+        password,
+        ip,
+        acid,
+        token,
+    )
+    .expect("default profile is valid")
+}
+
+pub fn param_i_with(
+    profile: &EncodingProfile,
+    username: &str,
+    password: &str,
+    ip: &str,
+    acid: i32,
+    token: &str,
+) -> Result<String> {
     let info = serde_json::json!({
         "username": username,
         "password": password,
         "ip": ip,
         "acid": acid,
-        "enc_ver": "srun_bx1",
+        "enc_ver": profile.enc_ver,
     })
     .to_string();
     let xen = x_encode(info.as_str(), token);
-    String::from("{SRBX1}") + BASE64_ENGINE.encode(xen).as_str()
+    Ok(profile.prefix.clone() + profile.engine()?.encode(xen).as_str())
+}
+
+#[test]
+fn test_scan_profile() {
+    let js = r#"var enc = "srun_bx2"; info: { enc_ver: 'srun_bx2' }, _ALPHA = "LVoJPiCN2R8G90yg+hmFHuacZ1OWMnrsSTXkYpUq/3dlbfKwv6xztjI7DeBE45QA""#;
+    let profile = EncodingProfile::scan(js).unwrap();
+    assert_eq!(profile.enc_ver, "srun_bx2");
+    assert_eq!(profile.prefix, "{SRBX2}");
+    assert_eq!(profile.alphabet, BASE64_ALPHABET);
+    assert_eq!(EncodingProfile::scan("<html></html>"), None);
 }